@@ -0,0 +1,772 @@
+// The APU mixes four sound channels into a left & right output:
+//
+// Channel | Registers       | Sound
+// -------------------------------------------------------
+//  1      | NR10 - NR14     | Square wave with frequency sweep
+//  2      | NR21 - NR24     | Square wave
+//  3      | NR30 - NR34     | 32 4-bit samples from wave RAM
+//  4      | NR41 - NR44     | Noise from a linear feedback shift register
+//
+// Registers:
+// 0xFF10 : NR10 - Sweep period (6-4), negate (3), shift (2-0)
+// 0xFFx1 : NRx1 - Duty (7-6), length load (5-0; 7-0 for channel 3)
+// 0xFFx2 : NRx2 - Initial volume (7-4), envelope add (3), period (2-0)
+//                 (channel 3: NR30 bit 7 is the DAC, NR32 bits 6-5 volume)
+// 0xFFx3 : NRx3 - Frequency low byte (channel 4: clock shift (7-4), width
+//                 mode (3), divisor code (2-0))
+// 0xFFx4 : NRx4 - Trigger (7), length enable (6), frequency high bits (2-0)
+// 0xFF24 : NR50 - Left volume (6-4), right volume (2-0)
+// 0xFF25 : NR51 - Left enables (7-4) & right enables (3-0) of channels 4-1
+// 0xFF26 : NR52 - Power (7), channel 4-1 on flags (3-0) (Read Only)
+// 0xFF30 - 0xFF3F : Wave RAM
+//
+// The frame sequencer is clocked at 512 Hz by the falling edge of bit 12 of
+// the timer's divider (bit 13 in double speed mode), so writing DIV can clock
+// it early. It steps through 8 steps; the length counters are clocked on every
+// even step (256 Hz), the sweep on steps 2 and 6 (128 Hz) and the volume
+// envelopes on step 7 (64 Hz).
+//
+// Each channel outputs a digital value from 0 - 15 which its DAC converts to
+// an analog value from -1 to 1; a channel whose DAC is off outputs 0. Samples
+// are taken at the host's sample rate and stored interleaved as left, right.
+
+use std::collections::VecDeque;
+
+/// APU clock cycles per second
+const CLOCK_RATE: u32 = 4_194_304;
+/// Bit of the timer's divider which clocks the frame sequencer (512 Hz)
+const DIV_BIT: u16 = 0x1000;
+/// Samples kept when the host doesn't drain them (one second at 48 kHz); the
+/// oldest ones are dropped
+const MAX_SAMPLES: usize = 2 * 48_000;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Bits which always read as 1 in 0xFF10 - 0xFF2F
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40 - NR44
+    0x00, 0x00, 0x70,             // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// Converts the digital output of a channel to an analog value
+fn dac(enabled: bool, digital: u8) -> f32 {
+    if enabled {
+        digital as f32 / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Length counter; disables its channel when it reaches 0
+struct Length {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn new(max: u16) -> Length {
+        Length { max, counter: 0, enabled: false }
+    }
+
+    fn load(&mut self, val: u8) {
+        self.counter = self.max - val as u16;
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Returns false once the counter expired
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+}
+
+/// Volume envelope of the square & noise channels
+struct Envelope {
+    initial: u8,
+    add: bool,
+    period: u8,
+    timer: u8,
+    volume: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope { initial: 0, add: false, period: 0, timer: 0, volume: 0 }
+    }
+
+    fn write(&mut self, val: u8) {
+        self.initial = val >> 4;
+        self.add = val & 0x08 != 0;
+        self.period = val & 0x07;
+    }
+
+    /// Returns if the DAC of the channel is on
+    fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.add
+    }
+
+    fn trigger(&mut self) {
+        self.timer = self.period;
+        self.volume = self.initial;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.add && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.add && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+/// Channels 1 & 2
+struct Square {
+    enabled: bool,
+    duty: u8,
+    duty_pos: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    // Frequency sweep, only used by channel 1
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow_frequency: u16,
+}
+
+impl Square {
+    fn new() -> Square {
+        Square {
+            enabled: false,
+            duty: 0,
+            duty_pos: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            shadow_frequency: 0,
+        }
+    }
+
+    /// Writes NRx0 - NRx4, `reg` being the x
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.sweep_period = (val >> 4) & 0x07;
+                self.sweep_negate = val & 0x08 != 0;
+                self.sweep_shift = val & 0x07;
+            },
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val & 0x3F);
+            },
+            2 => {
+                self.envelope.write(val);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => { self.frequency = self.frequency & 0x700 | val as u16; },
+            _ => {
+                self.frequency = self.frequency & 0xFF | (val as u16 & 0x07) << 8;
+                self.length.enabled = val & 0x40 != 0;
+                if val & 0x80 != 0 {
+                    self.trigger();
+                }
+            },
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.timer = (2048 - self.frequency as u32) * 4;
+        self.envelope.trigger();
+        self.shadow_frequency = self.frequency;
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+        if self.sweep_shift != 0 {
+            self.sweep_frequency();
+        }
+    }
+
+    /// Calculates the next frequency of the sweep, disabling the channel
+    /// when it overflows.
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift;
+        let frequency = if self.sweep_negate {
+            self.shadow_frequency.wrapping_sub(delta)
+        } else {
+            self.shadow_frequency + delta
+        };
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer > 0 {
+            return;
+        }
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        if self.sweep_enabled && self.sweep_period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= 2047 && self.sweep_shift != 0 {
+                self.frequency = frequency;
+                self.shadow_frequency = frequency;
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+        } else {
+            self.timer = (2048 - self.frequency as u32) * 4;
+            self.duty_pos = (self.duty_pos + 1) & 0x07;
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let digital = if self.enabled {
+            DUTY_PATTERNS[self.duty as usize][self.duty_pos as usize] * self.envelope.volume
+        } else {
+            0
+        };
+        dac(self.envelope.dac_enabled(), digital)
+    }
+}
+
+/// Channel 3
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    volume: u8, // NR32 bits 6-5
+    frequency: u16,
+    timer: u32,
+    position: u8, // sample (nibble) of wave RAM being played
+    sample: u8,
+    length: Length,
+    ram: [u8; 0x10],
+}
+
+impl Wave {
+    fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: Length::new(256),
+            ram: [0; 0x10],
+        }
+    }
+
+    /// Writes NR30 - NR34, `reg` being the last digit
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.dac_enabled = val & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => { self.length.load(val); },
+            2 => { self.volume = (val >> 5) & 0x03; },
+            3 => { self.frequency = self.frequency & 0x700 | val as u16; },
+            _ => {
+                self.frequency = self.frequency & 0xFF | (val as u16 & 0x07) << 8;
+                self.length.enabled = val & 0x40 != 0;
+                if val & 0x80 != 0 {
+                    self.enabled = self.dac_enabled;
+                    self.length.trigger();
+                    self.timer = (2048 - self.frequency as u32) * 2;
+                    self.position = 0;
+                }
+            },
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+        } else {
+            self.timer = (2048 - self.frequency as u32) * 2;
+            self.position = (self.position + 1) & 0x1F;
+            let byte = self.ram[(self.position / 2) as usize];
+            self.sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0x0F };
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let digital = if self.enabled && self.volume != 0 {
+            self.sample >> (self.volume - 1)
+        } else {
+            0
+        };
+        dac(self.dac_enabled, digital)
+    }
+}
+
+/// Channel 4
+struct Noise {
+    enabled: bool,
+    clock_shift: u8,
+    width_mode: bool, // 7-bit instead of 15-bit LFSR
+    divisor: u8,
+    lfsr: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn new() -> Noise {
+        Noise {
+            enabled: false,
+            clock_shift: 0,
+            width_mode: false,
+            divisor: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    /// Writes NR41 - NR44, `reg` being the last digit
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => { },
+            1 => { self.length.load(val & 0x3F); },
+            2 => {
+                self.envelope.write(val);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => {
+                self.clock_shift = val >> 4;
+                self.width_mode = val & 0x08 != 0;
+                self.divisor = val & 0x07;
+            },
+            _ => {
+                self.length.enabled = val & 0x40 != 0;
+                if val & 0x80 != 0 {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.length.trigger();
+                    self.timer = self.period();
+                    self.envelope.trigger();
+                    self.lfsr = 0x7FFF;
+                }
+            },
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor as usize] << self.clock_shift
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period();
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = self.lfsr >> 1 | bit << 14;
+        if self.width_mode {
+            self.lfsr = self.lfsr & !0x40 | bit << 6;
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let digital = if self.enabled {
+            (!self.lfsr & 0x01) as u8 * self.envelope.volume
+        } else {
+            0
+        };
+        dac(self.envelope.dac_enabled(), digital)
+    }
+}
+
+pub struct APU {
+    power: bool, // NR52 bit 7
+    regs: [u8; 0x20], // last written values of 0xFF10 - 0xFF2F
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    frame_step: u8,
+    sample_rate: u32,
+    sample_cycles: u32, // fraction of a sample, in sample rate units
+    samples: VecDeque<f32>, // interleaved left & right samples
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            power: false,
+            regs: [0; 0x20],
+            square1: Square::new(),
+            square2: Square::new(),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            frame_step: 0,
+            sample_rate: 48_000,
+            sample_cycles: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Sets up channel 1 from the registers left behind by the boot ROM. Its
+    /// sound has faded out by then, but the channel is still on.
+    pub fn skip_boot(&mut self) {
+        // Only some boot ROMs play the sound
+        if self.regs[4] & 0x80 == 0 {
+            return;
+        }
+        for reg in 0..4 {
+            self.square1.write(reg, self.regs[reg as usize]);
+        }
+        self.square1.write(4, self.regs[4] & 0x7F);
+        self.square1.enabled = true;
+    }
+
+    /// Sets the rate in Hz at which samples are produced
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_cycles = 0;
+    }
+
+    /// Returns the rate in Hz at which samples are produced
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the samples produced since the last call, interleaved as
+    /// left, right in the range -1 to 1.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    /// Advances the APU by `cycles` clock cycles (at normal speed); `div` is
+    /// the timer's divider before those cycles, counting at normal speed.
+    pub fn step(&mut self, cycles: u32, div: u16) {
+        for i in 0..cycles {
+            if self.power {
+                let div = div.wrapping_add(i as u16);
+                if div & DIV_BIT != 0 && div.wrapping_add(1) & DIV_BIT == 0 {
+                    self.clock_frame_sequencer();
+                }
+                self.square1.tick();
+                self.square2.tick();
+                self.wave.tick();
+                self.noise.tick();
+            }
+            self.sample_cycles += self.sample_rate;
+            if self.sample_cycles >= CLOCK_RATE {
+                self.sample_cycles -= CLOCK_RATE;
+                self.push_sample();
+            }
+        }
+    }
+
+    /// Called when the timer's divider is cleared while it's `div`; clearing
+    /// the frame sequencer's bit clocks it
+    pub fn reset_div(&mut self, div: u16) {
+        if self.power && div & DIV_BIT != 0 {
+            self.clock_frame_sequencer();
+        }
+    }
+
+    /// Reads one of the sound registers or wave RAM (0xFF10 - 0xFF3F)
+    pub fn rb(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26 => {
+                0x70 | (self.power as u8) << 7
+                     | (self.noise.enabled as u8) << 3
+                     | (self.wave.enabled as u8) << 2
+                     | (self.square2.enabled as u8) << 1
+                     | self.square1.enabled as u8
+            },
+            0xFF10 ..= 0xFF2F => {
+                let reg = (addr - 0xFF10) as usize;
+                self.regs[reg] | READ_MASKS[reg]
+            },
+            0xFF30 ..= 0xFF3F => self.wave.ram[(addr - 0xFF30) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    /// Sets one of the sound registers or wave RAM (0xFF10 - 0xFF3F) without
+    /// the side effects of a write: channels aren't reconfigured or
+    /// triggered and NR52 only sets the power flag.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF26 => { self.power = val & 0x80 != 0; },
+            0xFF10 ..= 0xFF2F => { self.regs[(addr - 0xFF10) as usize] = val; },
+            0xFF30 ..= 0xFF3F => { self.wave.ram[(addr - 0xFF30) as usize] = val; },
+            _ => { },
+        }
+    }
+
+    /// Writes one of the sound registers or wave RAM (0xFF10 - 0xFF3F)
+    /// While the APU is powered off only NR52 & wave RAM are writable.
+    pub fn wb(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF26 => {
+                let power = val & 0x80 != 0;
+                if self.power && !power {
+                    self.power_off();
+                } else if !self.power && power {
+                    self.frame_step = 0;
+                }
+                self.power = power;
+            },
+            0xFF30 ..= 0xFF3F => { self.wave.ram[(addr - 0xFF30) as usize] = val; },
+            _ if !self.power => { },
+            0xFF10 ..= 0xFF2F => {
+                self.regs[(addr - 0xFF10) as usize] = val;
+                match addr {
+                    0xFF10 ..= 0xFF14 => { self.square1.write(addr - 0xFF10, val); },
+                    // channel 2 has no sweep (NR20)
+                    0xFF16 ..= 0xFF19 => { self.square2.write(addr - 0xFF15, val); },
+                    0xFF1A ..= 0xFF1E => { self.wave.write(addr - 0xFF1A, val); },
+                    0xFF1F ..= 0xFF23 => { self.noise.write(addr - 0xFF1F, val); },
+                    _ => { },
+                }
+            },
+            _ => { },
+        }
+    }
+
+    /// Turning the APU off clears all of its registers
+    fn power_off(&mut self) {
+        let wave_ram = self.wave.ram;
+        self.regs = [0; 0x20];
+        self.square1 = Square::new();
+        self.square2 = Square::new();
+        self.wave = Wave::new();
+        self.wave.ram = wave_ram;
+        self.noise = Noise::new();
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_step & 0x01 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.frame_step = (self.frame_step + 1) & 0x07;
+    }
+
+    /// Mixes the channels according to NR50 & NR51 and stores the sample
+    fn push_sample(&mut self) {
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+            self.samples.pop_front();
+        }
+        let (left, right) = if self.power {
+            let outputs = [
+                self.square1.output(),
+                self.square2.output(),
+                self.wave.output(),
+                self.noise.output(),
+            ];
+            let nr50 = self.regs[0x14];
+            let nr51 = self.regs[0x15];
+            let mut left = 0.0;
+            let mut right = 0.0;
+            for (channel, output) in outputs.iter().enumerate() {
+                if nr51 & (0x10 << channel) != 0 {
+                    left += output;
+                }
+                if nr51 & (0x01 << channel) != 0 {
+                    right += output;
+                }
+            }
+            let left_volume = ((nr50 >> 4) & 0x07) as f32 + 1.0;
+            let right_volume = (nr50 & 0x07) as f32 + 1.0;
+            (left / 4.0 * left_volume / 8.0, right / 4.0 * right_volume / 8.0)
+        } else {
+            (0.0, 0.0)
+        };
+        self.samples.push_back(left);
+        self.samples.push_back(right);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a powered on APU
+    fn apu() -> APU {
+        let mut apu = APU::new();
+        apu.wb(0xFF26, 0x80);
+        apu
+    }
+
+    /// Clocks the frame sequencer `steps` times
+    fn frame_steps(apu: &mut APU, steps: u32) {
+        for _ in 0..steps {
+            apu.step(DIV_BIT as u32 * 2, 0x0000);
+        }
+    }
+
+    #[test]
+    fn length_counter_disables_the_channel() {
+        let mut apu = apu();
+        apu.wb(0xFF12, 0xF0);
+        apu.wb(0xFF11, 0x3E);
+        apu.wb(0xFF14, 0xC0);
+        frame_steps(&mut apu, 1);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x01);
+        // the length counter is only clocked on even steps
+        frame_steps(&mut apu, 1);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x01);
+        frame_steps(&mut apu, 1);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn div_reset_clocks_the_frame_sequencer() {
+        let mut apu = apu();
+        apu.wb(0xFF12, 0xF0);
+        apu.wb(0xFF11, 0x3F);
+        apu.wb(0xFF14, 0xC0);
+        apu.reset_div(DIV_BIT - 1);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x01);
+        apu.reset_div(DIV_BIT);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn sweep_overflow_disables_the_channel() {
+        let mut apu = apu();
+        apu.wb(0xFF12, 0xF0);
+        apu.wb(0xFF10, 0x11);
+        // 0x7FF + 0x3FF overflows right away
+        apu.wb(0xFF13, 0xFF);
+        apu.wb(0xFF14, 0x87);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x00);
+
+        // 0x500 + 0x280 doesn't, but the next one does on the first sweep
+        apu.wb(0xFF13, 0x00);
+        apu.wb(0xFF14, 0x85);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x01);
+        frame_steps(&mut apu, 2);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x01);
+        frame_steps(&mut apu, 1);
+        assert_eq!(apu.rb(0xFF26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn dac_off_disables_the_channel() {
+        let mut apu = apu();
+        apu.wb(0xFF17, 0xF0);
+        apu.wb(0xFF19, 0x80);
+        assert_eq!(apu.rb(0xFF26) & 0x02, 0x02);
+        apu.wb(0xFF17, 0x00);
+        assert_eq!(apu.rb(0xFF26) & 0x02, 0x00);
+        // triggering doesn't turn it back on
+        apu.wb(0xFF19, 0x80);
+        assert_eq!(apu.rb(0xFF26) & 0x02, 0x00);
+
+        apu.wb(0xFF1A, 0x80);
+        apu.wb(0xFF1E, 0x80);
+        assert_eq!(apu.rb(0xFF26) & 0x04, 0x04);
+        apu.wb(0xFF1A, 0x00);
+        assert_eq!(apu.rb(0xFF26) & 0x04, 0x00);
+    }
+
+    #[test]
+    fn keeps_the_newest_samples() {
+        let mut apu = APU::new();
+        apu.set_sample_rate(CLOCK_RATE);
+        apu.step(MAX_SAMPLES as u32 / 2, 0x0000);
+        apu.wb(0xFF26, 0x80);
+        apu.wb(0xFF24, 0x77);
+        apu.wb(0xFF25, 0xFF);
+        apu.wb(0xFF12, 0xF0);
+        apu.step(10, 0x0000);
+        let samples = apu.drain_samples();
+        assert_eq!(samples.len(), MAX_SAMPLES);
+        assert!(samples[..MAX_SAMPLES - 20].iter().all(|&s| s == 0.0));
+        assert!(samples[MAX_SAMPLES - 20..].iter().all(|&s| s != 0.0));
+        assert!(apu.drain_samples().is_empty());
+    }
+}
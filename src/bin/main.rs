@@ -32,9 +32,16 @@ fn main () {
     println!("Rom Length: {} Bytes.", boot_rom.len());
 
     // Run the emu with the rom data
-    let _ = state.load_bytes(&boot_rom);
+    if let Err(err) = state.load_bytes(&boot_rom) {
+        println!("{}", err);
+        return;
+    }
+    state.set_trace_callback(|addr, inst| println!("0x{:04x}: {}", addr, inst));
 
     for _ in 0..100 {
-        state.step();
+        if let Err(err) = state.step() {
+            println!("{}", err);
+            break;
+        }
     }
 }
@@ -0,0 +1,193 @@
+// The boot ROM is mapped over the start of the cartridge at power on. It
+// scrolls in the Nintendo logo, checks the cartridge header and then unmaps
+// itself by writing to 0xFF50, after which the cartridge starts at 0x0100.
+//
+// Boot ROM | Size  | Mapped to
+// -----------------------------------------------
+//  DMG0    |  256  | [0000 -> 00FF]
+//  DMG     |  256  | [0000 -> 00FF]
+//  MGB     |  256  | [0000 -> 00FF]
+//  SGB     |  256  | [0000 -> 00FF]
+//  SGB2    |  256  | [0000 -> 00FF]
+//  CGB     | 2304  | [0000 -> 00FF] & [0200 -> 08FF]
+//
+// Dumps of the boot ROMs are recognized by their MD5 digest. When the boot
+// ROM is skipped the registers are set to the values each model's boot ROM
+// leaves behind.
+
+use md5;
+use rgb_error::RgbError;
+
+/// The DMG boot ROM
+pub const DMG_BOOT_ROM: [u8; 0x100] = [
+    0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32,
+    0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
+    0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3,
+    0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
+    0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1A,
+    0xCD, 0x95, 0x00, 0xCD, 0x96, 0x00, 0x13, 0x7B,
+    0xFE, 0x34, 0x20, 0xF3, 0x11, 0xD8, 0x00, 0x06,
+    0x08, 0x1A, 0x13, 0x22, 0x23, 0x05, 0x20, 0xF9,
+    0x3E, 0x19, 0xEA, 0x10, 0x99, 0x21, 0x2F, 0x99,
+    0x0E, 0x0C, 0x3D, 0x28, 0x08, 0x32, 0x0D, 0x20,
+    0xF9, 0x2E, 0x0F, 0x18, 0xF3, 0x67, 0x3E, 0x64,
+    0x57, 0xE0, 0x42, 0x3E, 0x91, 0xE0, 0x40, 0x04,
+    0x1E, 0x02, 0x0E, 0x0C, 0xF0, 0x44, 0xFE, 0x90,
+    0x20, 0xFA, 0x0D, 0x20, 0xF7, 0x1D, 0x20, 0xF2,
+    0x0E, 0x13, 0x24, 0x7C, 0x1E, 0x83, 0xFE, 0x62,
+    0x28, 0x06, 0x1E, 0xC1, 0xFE, 0x64, 0x20, 0x06,
+    0x7B, 0xE2, 0x0C, 0x3E, 0x87, 0xE2, 0xF0, 0x42,
+    0x90, 0xE0, 0x42, 0x15, 0x20, 0xD2, 0x05, 0x20,
+    0x4F, 0x16, 0x20, 0x18, 0xCB, 0x4F, 0x06, 0x04,
+    0xC5, 0xCB, 0x11, 0x17, 0xC1, 0xCB, 0x11, 0x17,
+    0x05, 0x20, 0xF5, 0x22, 0x23, 0x22, 0x23, 0xC9,
+    // start: Scrolling Nintendo graphic
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B,
+    0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
+    0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+    // end: Scrolling Nintendo graphic
+    0x3c, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C,
+    0x21, 0x04, 0x01, 0x11, 0xA8, 0x00, 0x1A, 0x13,
+    0xBE, 0x20, 0xFE, 0x23, 0x7D, 0xFE, 0x34, 0x20,
+    0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20,
+    0xFB, 0x86, 0x20, 0xFE, 0x3E, 0x01, 0xE0, 0x50
+];
+
+/// Returns the Nintendo logo, which the boot ROM compares with the one in the
+/// cartridge header
+pub fn nintendo_logo() -> &'static [u8] {
+    &DMG_BOOT_ROM[0xA8..0xD8]
+}
+
+/// Size of the DMG, MGB & SGB boot ROMs
+pub const BOOT_ROM_SIZE: usize = 0x100;
+/// Size of the CGB boot ROM
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// MD5 digests of the known boot ROM dumps
+const KNOWN_BOOT_ROMS: [(Model, &str); 6] = [
+    (Model::DMG0, "a8f84a0ac44da5d3f0ee19f9cea80a8c"),
+    (Model::DMG, "32fbbd84168d3482956eb3c5051637f5"),
+    (Model::MGB, "71a378e71ff30b2d8a1f02bf5c7896aa"),
+    (Model::SGB, "d574d4f9c12f305074798f54c091a8b4"),
+    (Model::SGB2, "e0430bca9925fb9882148fd2dc2418c1"),
+    (Model::CGB, "dbfce9db9deaa2567f6a84fde55f9680"),
+];
+
+/// I/O registers as left by the DMG, MGB & CGB boot ROMs, written in order
+/// The sound registers are those of the final "ding".
+const POST_BOOT_IO: [(u16, u8); 12] = [
+    (0xFF00, 0x00), // P1
+    (0xFF26, 0xF1), // NR52
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xC1), // NR13
+    (0xFF14, 0x87), // NR14
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF47, 0xFC), // BGP
+    (0xFF0F, 0xE1), // IF
+];
+
+/// I/O registers as left by the SGB boot ROMs, which turn on the sound
+/// without playing it and leave both joypad groups deselected
+const SGB_POST_BOOT_IO: [(u16, u8); 7] = [
+    (0xFF00, 0x30), // P1
+    (0xFF26, 0xF0), // NR52
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF47, 0xFC), // BGP
+    (0xFF0F, 0xE1), // IF
+];
+
+/// The Game Boy models
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Model {
+    DMG0, // early Japanese DMG
+    DMG,
+    MGB, // Game Boy Pocket
+    SGB,
+    SGB2,
+    CGB,
+}
+
+impl Model {
+    /// Returns the model of a boot ROM dump
+    pub fn from_boot_rom(rom: &[u8]) -> Result<Model, RgbError> {
+        if rom.len() != BOOT_ROM_SIZE && rom.len() != CGB_BOOT_ROM_SIZE {
+            return Err(RgbError::BootRomLength(rom.len()));
+        }
+        let digest = md5::digest(rom).iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        KNOWN_BOOT_ROMS.iter()
+            .find(|&&(_, known)| known == digest)
+            .map(|&(model, _)| model)
+            .ok_or(RgbError::BootRomBad)
+    }
+
+    /// Returns if the model is a Color Game Boy
+    pub fn is_cgb(self) -> bool {
+        self == Model::CGB
+    }
+
+    /// Returns AF, BC, DE & HL as left by the boot ROM
+    /// `cgb_mode` selects between the CGB's color & DMG compatibility mode.
+    pub fn post_boot_registers(self, cgb_mode: bool) -> [u16; 4] {
+        match self {
+            Model::DMG0 => [0x0100, 0xFF13, 0x00C1, 0x8403],
+            Model::DMG  => [0x01B0, 0x0013, 0x00D8, 0x014D],
+            Model::MGB  => [0xFFB0, 0x0013, 0x00D8, 0x014D],
+            Model::SGB  => [0x0100, 0x0014, 0x0000, 0xC060],
+            Model::SGB2 => [0xFF00, 0x0014, 0x0000, 0xC060],
+            Model::CGB if cgb_mode => [0x1180, 0x0000, 0xFF56, 0x000D],
+            Model::CGB  => [0x1180, 0x0000, 0x0008, 0x007C],
+        }
+    }
+
+    /// Returns the I/O registers as left by the boot ROM, in the order they
+    /// have to be written
+    pub fn post_boot_io(self) -> &'static [(u16, u8)] {
+        match self {
+            Model::SGB | Model::SGB2 => &SGB_POST_BOOT_IO,
+            _ => &POST_BOOT_IO,
+        }
+    }
+
+    /// Returns the internal divider of the timer as left by the boot ROM
+    pub fn post_boot_div(self) -> u16 {
+        match self {
+            Model::DMG0 => 0x1830,
+            Model::DMG | Model::MGB => 0xABCC,
+            Model::SGB | Model::SGB2 => 0x0000,
+            Model::CGB => 0x1EA0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_boot_roms() {
+        assert_eq!(Model::from_boot_rom(&DMG_BOOT_ROM).unwrap(), Model::DMG);
+
+        let mut rom = DMG_BOOT_ROM;
+        rom[0xFF] ^= 0x01;
+        match Model::from_boot_rom(&rom) {
+            Err(RgbError::BootRomBad) => { },
+            other => panic!("expected BootRomBad, got {:?}", other),
+        }
+        match Model::from_boot_rom(&DMG_BOOT_ROM[..0xFF]) {
+            Err(RgbError::BootRomLength(0xFF)) => { },
+            other => panic!("expected BootRomLength, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// MBC1 - up to 2MB of ROM and 32KB of RAM
+//
+// 0x0000 - 0x1FFF : RAM Enable (0x0A in the lower 4 bits enables the RAM)
+// 0x2000 - 0x3FFF : ROM Bank Number, lower 5 bits (BANK1)
+//                   Writing 0 selects bank 1, so banks 0x20, 0x40 & 0x60
+//                   can't be mapped to [4000 -> 7FFF].
+// 0x4000 - 0x5FFF : RAM Bank Number or upper 2 bits of the ROM bank (BANK2)
+// 0x6000 - 0x7FFF : Banking Mode Select
+//                   0 - BANK2 only selects the upper ROM bank bits
+//                   1 - BANK2 also selects the RAM bank and the bank mapped
+//                       to [0000 -> 3FFF]
+//
+// MBC1M multicarts connect BANK1 with only 4 bits to the ROM, so BANK2
+// selects one of four 256KB games. They are detected by a second Nintendo
+// logo in the header of bank 0x10.
+
+use boot;
+use cartridge::{Cartridge, load_ram, ROM_BANK_SIZE, rom_byte, rom_bank_offset, ram_bank_offset};
+
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    multicart: bool, // MBC1M
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+        let multicart = Mbc1::is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    /// A 1MB ROM with the Nintendo logo also in bank 0x10 is a MBC1M
+    fn is_multicart(rom: &[u8]) -> bool {
+        let second = 0x10 * ROM_BANK_SIZE;
+        rom.len() == 0x40 * ROM_BANK_SIZE
+            && rom[second + 0x0104..second + 0x0134] == *boot::nintendo_logo()
+    }
+
+    /// Returns the number of bits of BANK1 connected to the ROM
+    fn bank1_bits(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    /// Returns the bank mapped to [0000 -> 3FFF]
+    fn low_bank(&self) -> usize {
+        if self.mode {
+            (self.bank2 << self.bank1_bits()) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Returns the bank mapped to [4000 -> 7FFF]
+    fn high_bank(&self) -> usize {
+        let mask = (1 << self.bank1_bits()) - 1;
+        ((self.bank2 << self.bank1_bits()) | (self.bank1 & mask)) as usize
+    }
+
+    /// Returns the offset into the RAM of `addr`
+    fn ram_offset(&self, addr: u16) -> usize {
+        ram_bank_offset(&self.ram, self.ram_bank().unwrap_or(0)) + (addr & 0x1FFF) as usize
+    }
+}
+
+impl Cartridge for Mbc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = self.rom_bank(addr);
+        rom_byte(&self.rom, rom_bank_offset(&self.rom, bank) + (addr & 0x3FFF) as usize)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => { self.ram_enabled = val & 0x0F == 0x0A; },
+            0x2000 ..= 0x3FFF => {
+                // The zero check uses all 5 bits, also on MBC1M
+                self.bank1 = if val & 0x1F == 0 { 1 } else { val & 0x1F };
+            },
+            0x4000 ..= 0x5FFF => { self.bank2 = val & 0x03; },
+            _ => { self.mode = val & 0x01 != 0; },
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(addr) % self.ram.len()]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(addr) % self.ram.len();
+        self.ram[offset] = val;
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn rom(&self) -> &[u8] { &self.rom }
+
+    fn rom_mut(&mut self) -> &mut [u8] { &mut self.rom }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { self.low_bank() } else { self.high_bank() }
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        Some(if self.mode { self.bank2 as usize } else { 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::tests::{banked_rom, bank_at};
+
+    #[test]
+    fn rom_banking() {
+        let mut mbc = Mbc1::new(banked_rom(0x80), 0);
+        assert_eq!(bank_at(&mbc, 0x0000), 0x00);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x01);
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x1F);
+        // Only the lower 5 bits are checked for 0, so 0x20 is skipped
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x21);
+        assert_eq!(bank_at(&mbc, 0x0000), 0x00);
+        // Mode 1 also maps BANK2 to [0000 -> 3FFF]
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(bank_at(&mbc, 0x0000), 0x20);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x21);
+    }
+
+    #[test]
+    fn small_roms_wrap() {
+        let mut mbc = Mbc1::new(banked_rom(0x08), 0);
+        mbc.write_rom(0x2000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 0x02);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let mut mbc = Mbc1::new(banked_rom(0x04), 0x8000);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA000, 0x34);
+        // BANK2 only selects the RAM bank in mode 1
+        assert_eq!(mbc.ram()[0x0000], 0x34);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_ram(0xA000, 0x56);
+        assert_eq!(mbc.ram()[0x4000], 0x56);
+        assert_eq!(mbc.read_ram(0xA000), 0x56);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn multicart() {
+        let mut rom = banked_rom(0x40);
+        for game in 0..4 {
+            let header = game * 0x10 * ROM_BANK_SIZE + 0x0104;
+            rom[header..header + 0x30].copy_from_slice(boot::nintendo_logo());
+        }
+        let mut mbc = Mbc1::new(rom, 0);
+        assert!(mbc.multicart);
+
+        // BANK1 has 4 bits & BANK2 selects one of the 256KB games
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x02);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x32);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(bank_at(&mbc, 0x0000), 0x30);
+        // A 0 in the lower 4 bits isn't replaced with 1
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x30);
+    }
+
+    #[test]
+    fn repeated_headers_are_not_a_multicart() {
+        let mut rom = banked_rom(0x40);
+        for bank in [0x00, 0x10].iter() {
+            let header = bank * ROM_BANK_SIZE + 0x0104;
+            rom[header..header + 0x30].copy_from_slice(&[0xCE; 0x30]);
+        }
+        assert!(!Mbc1::new(rom, 0).multicart);
+    }
+}
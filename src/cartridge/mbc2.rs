@@ -0,0 +1,122 @@
+// MBC2 - up to 256KB of ROM and 512x4 bits of RAM built into the MBC
+//
+// 0x0000 - 0x3FFF : Address bit 8 selects the register
+//                   0 - RAM Enable (0x0A in the lower 4 bits enables the RAM)
+//                   1 - ROM Bank Number (4 bits, writing 0 selects bank 1)
+//
+// The RAM only has 512 addresses, which repeat through [A000 -> BFFF]. Only
+// the lower 4 bits of each byte exist; the upper ones read as 1.
+
+use cartridge::{Cartridge, load_ram, rom_byte, rom_bank_offset};
+
+/// Half-bytes of RAM in the MBC
+const RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: [u8; RAM_SIZE],
+    ram_enabled: bool,
+    bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            rom,
+            ram: [0; RAM_SIZE],
+            ram_enabled: false,
+            bank: 1,
+        }
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = self.rom_bank(addr);
+        rom_byte(&self.rom, rom_bank_offset(&self.rom, bank) + (addr & 0x3FFF) as usize)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x3FFF if addr & 0x0100 == 0 => {
+                self.ram_enabled = val & 0x0F == 0x0A;
+            },
+            0x0000 ..= 0x3FFF => {
+                self.bank = if val & 0x0F == 0 { 1 } else { val & 0x0F };
+            },
+            _ => { },
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        self.ram[(addr as usize) & (RAM_SIZE - 1)] | 0xF0
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            self.ram[(addr as usize) & (RAM_SIZE - 1)] = val & 0x0F;
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        for byte in self.ram.iter_mut() {
+            *byte &= 0x0F;
+        }
+    }
+
+    fn rom(&self) -> &[u8] { &self.rom }
+
+    fn rom_mut(&mut self) -> &mut [u8] { &mut self.rom }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.bank as usize }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::tests::{banked_rom, bank_at};
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let mut mbc = Mbc2::new(banked_rom(0x10));
+        assert_eq!(bank_at(&mbc, 0x4000), 0x01);
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x05);
+        mbc.write_rom(0x0100, 0x10);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x01);
+        // Without bit 8 the write goes to the RAM enable register
+        mbc.write_rom(0x2000, 0x07);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x01);
+        assert!(!mbc.ram_enabled);
+        mbc.write_rom(0x3EFF, 0x0A);
+        assert!(mbc.ram_enabled);
+    }
+
+    #[test]
+    fn half_byte_ram() {
+        let mut mbc = Mbc2::new(banked_rom(0x02));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0xAB);
+        assert_eq!(mbc.read_ram(0xA000), 0xFB);
+        // The 512 addresses repeat
+        assert_eq!(mbc.read_ram(0xA200), 0xFB);
+        assert_eq!(mbc.read_ram(0xBE00), 0xFB);
+
+        mbc.load(&[0xFF; RAM_SIZE]);
+        assert_eq!(mbc.save(), vec![0x0F; RAM_SIZE]);
+    }
+}
@@ -0,0 +1,504 @@
+// MBC3 - up to 2MB of ROM, 32KB of RAM and an optional real time clock
+//
+// 0x0000 - 0x1FFF : RAM & RTC Enable (0x0A in the lower 4 bits enables them)
+// 0x2000 - 0x3FFF : ROM Bank Number (7 bits, writing 0 selects bank 1)
+// 0x4000 - 0x5FFF : RAM Bank Number (0x00 - 0x03) or RTC Register Select
+//                   (0x08 - 0x0C) mapped to [A000 -> BFFF]
+// 0x6000 - 0x7FFF : Latch Clock Data; writing 0x00 and then 0x01 copies the
+//                   clock to the latched registers which are read by the CPU
+//
+// RTC Registers:
+// 0x08 : RTC S  - Seconds (0 - 59)
+// 0x09 : RTC M  - Minutes (0 - 59)
+// 0x0A : RTC H  - Hours (0 - 23)
+// 0x0B : RTC DL - Lower 8 bits of the Day Counter
+// 0x0C : RTC DH - 0   - Bit 8 of the Day Counter
+//                 6   - Halt (0=Active, 1=Stop Timer)
+//                 7   - Day Counter Carry Bit (1=Counter Overflow)
+//
+// The counters only wrap at their limits when counting from the limit; a
+// counter that was set past its limit keeps counting up to the end of its
+// bits and wraps to 0 without carrying into the next counter.
+//
+// Save files have the clock appended in the 48 byte footer used by BGB &
+// VBA: the 5 clock registers and the 5 latched registers as little endian
+// 32-bit values followed by the 64-bit UNIX time of the save.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cartridge::{Cartridge, load_ram, rom_byte, rom_bank_offset, ram_bank_offset};
+
+/// Clock cycles per second of emulated time
+const CLOCK_RATE: u32 = 4_194_304;
+/// Bytes of the RTC footer in save files
+const FOOTER_SIZE: usize = 48;
+/// Older saves store the UNIX time in 32 bits
+const SHORT_FOOTER_SIZE: usize = 44;
+
+/// Masks of the bits present in the RTC registers S, M, H, DL & DH
+const REGISTER_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+
+/// The time source of the real time clock
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RtcClock {
+    Emulated, // counts the clock cycles of the emulator
+    Host, // follows the time of the host, also while not running
+}
+
+/// Returns the host's UNIX time in seconds
+fn host_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Clone)]
+struct Rtc {
+    clock: RtcClock,
+    regs: [u8; 5], // S, M, H, DL & DH
+    latched: [u8; 5],
+    latch_armed: bool, // 0x00 was written to the latch register
+    written: bool, // the registers were set since the save was written
+    cycles: u32, // emulated clock cycles into the current second
+    time: u64, // host time the registers are up to date with
+}
+
+impl Rtc {
+    fn new() -> Rtc {
+        Rtc {
+            clock: RtcClock::Host,
+            regs: [0; 5],
+            latched: [0; 5],
+            latch_armed: false,
+            written: false,
+            cycles: 0,
+            time: host_time(),
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.regs[4] & 0x40 != 0
+    }
+
+    fn set_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        self.clock = clock;
+        self.time = host_time();
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.clock != RtcClock::Emulated || self.halted() {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CLOCK_RATE {
+            self.cycles -= CLOCK_RATE;
+            self.advance_second();
+        }
+    }
+
+    /// Catches up with the host time
+    fn sync(&mut self) {
+        if self.clock != RtcClock::Host {
+            return;
+        }
+        let now = host_time();
+        if now > self.time && !self.halted() {
+            let seconds = now - self.time;
+            self.advance(seconds);
+        }
+        self.time = now;
+    }
+
+    /// Advances the clock by `seconds` at once
+    fn advance(&mut self, mut seconds: u64) {
+        // Counters set past their limits have to count up one by one
+        while seconds > 0 && (self.regs[0] > 59 || self.regs[1] > 59 || self.regs[2] > 23) {
+            self.advance_second();
+            seconds -= 1;
+        }
+        let days = (self.regs[4] as u64 & 0x01) << 8 | self.regs[3] as u64;
+        let total = self.regs[0] as u64 + self.regs[1] as u64 * 60
+            + self.regs[2] as u64 * 3600 + days * 86400 + seconds;
+        let days = total / 86400;
+        self.regs[0] = (total % 60) as u8;
+        self.regs[1] = (total / 60 % 60) as u8;
+        self.regs[2] = (total / 3600 % 24) as u8;
+        self.regs[3] = days as u8;
+        self.regs[4] = self.regs[4] & 0xFE | (days >> 8) as u8 & 0x01;
+        if days > 0x1FF {
+            self.regs[4] |= 0x80;
+        }
+    }
+
+    fn advance_second(&mut self) {
+        if self.regs[0] != 59 {
+            self.regs[0] = (self.regs[0] + 1) & REGISTER_MASKS[0];
+            return;
+        }
+        self.regs[0] = 0;
+        if self.regs[1] != 59 {
+            self.regs[1] = (self.regs[1] + 1) & REGISTER_MASKS[1];
+            return;
+        }
+        self.regs[1] = 0;
+        if self.regs[2] != 23 {
+            self.regs[2] = (self.regs[2] + 1) & REGISTER_MASKS[2];
+            return;
+        }
+        self.regs[2] = 0;
+        let days = ((self.regs[4] as u16 & 0x01) << 8 | self.regs[3] as u16) + 1;
+        self.regs[3] = days as u8;
+        self.regs[4] = self.regs[4] & 0xFE | (days >> 8) as u8 & 0x01;
+        if days > 0x1FF {
+            self.regs[4] |= 0x80;
+        }
+    }
+
+    fn write_latch(&mut self, val: u8) {
+        if self.latch_armed && val == 0x01 {
+            self.sync();
+            self.latched = self.regs;
+        }
+        self.latch_armed = val == 0x00;
+    }
+
+    fn read(&self, reg: u8) -> u8 {
+        self.latched[(reg - 0x08) as usize]
+    }
+
+    fn write(&mut self, reg: u8, val: u8) {
+        self.sync();
+        let index = (reg - 0x08) as usize;
+        if index == 0 {
+            self.cycles = 0;
+        }
+        self.regs[index] = val & REGISTER_MASKS[index];
+        self.written = true;
+    }
+
+    /// Sets a register without catching up with the time first
+    fn poke(&mut self, reg: u8, val: u8) {
+        let index = (reg - 0x08) as usize;
+        self.regs[index] = val & REGISTER_MASKS[index];
+    }
+
+    fn footer(&self) -> Vec<u8> {
+        let mut rtc = self.clone();
+        rtc.sync();
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+        for reg in rtc.regs.iter().chain(rtc.latched.iter()) {
+            footer.extend_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        footer.extend_from_slice(&host_time().to_le_bytes());
+        footer
+    }
+
+    fn load_footer(&mut self, footer: &[u8]) {
+        let word = |i: usize| {
+            u32::from_le_bytes([footer[i * 4], footer[i * 4 + 1], footer[i * 4 + 2], footer[i * 4 + 3]])
+        };
+        for (i, mask) in REGISTER_MASKS.iter().enumerate() {
+            self.regs[i] = word(i) as u8 & mask;
+            self.latched[i] = word(i + 5) as u8 & mask;
+        }
+        self.time = if footer.len() >= FOOTER_SIZE {
+            let mut time = [0; 8];
+            time.copy_from_slice(&footer[40..48]);
+            u64::from_le_bytes(time)
+        } else {
+            word(10) as u64
+        };
+        self.written = true;
+        // Account for the time passed since the save was written
+        self.sync();
+        if self.clock != RtcClock::Host {
+            self.time = host_time();
+        }
+    }
+}
+
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    ram_enabled: bool, // also enables the RTC registers
+    rom_bank: u8,
+    ram_bank: u8, // or RTC register (0x08 - 0x0C)
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    /// Returns the offset into the RAM of `addr`
+    fn ram_offset(&self, addr: u16) -> usize {
+        ram_bank_offset(&self.ram, self.ram_bank as usize) + (addr & 0x1FFF) as usize
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = Cartridge::rom_bank(self, addr);
+        rom_byte(&self.rom, rom_bank_offset(&self.rom, bank) + (addr & 0x3FFF) as usize)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => { self.ram_enabled = val & 0x0F == 0x0A; },
+            0x2000 ..= 0x3FFF => {
+                self.rom_bank = if val & 0x7F == 0 { 1 } else { val & 0x7F };
+            },
+            0x4000 ..= 0x5FFF => { self.ram_bank = val & 0x0F; },
+            _ => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(val);
+                }
+            },
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_bank, &self.rtc) {
+            (0x08 ..= 0x0C, Some(rtc)) => rtc.read(self.ram_bank),
+            (0x00 ..= 0x07, _) if !self.ram.is_empty() => {
+                self.ram[self.ram_offset(addr) % self.ram.len()]
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match (self.ram_bank, &mut self.rtc) {
+            (0x08 ..= 0x0C, Some(rtc)) => { rtc.write(self.ram_bank, val); },
+            (0x00 ..= 0x07, _) if !self.ram.is_empty() => {
+                let offset = self.ram_offset(addr) % self.ram.len();
+                self.ram[offset] = val;
+            },
+            _ => { },
+        }
+    }
+
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match (self.ram_bank, &self.rtc) {
+            (0x08 ..= 0x0C, Some(rtc)) => rtc.read(self.ram_bank),
+            (0x00 ..= 0x07, _) if !self.ram.is_empty() => {
+                self.ram[self.ram_offset(addr) % self.ram.len()]
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn poke_ram(&mut self, addr: u16, val: u8) {
+        match (self.ram_bank, &mut self.rtc) {
+            (0x08 ..= 0x0C, Some(rtc)) => { rtc.poke(self.ram_bank, val); },
+            (0x00 ..= 0x07, _) if !self.ram.is_empty() => {
+                let offset = self.ram_offset(addr) % self.ram.len();
+                self.ram[offset] = val;
+            },
+            _ => { },
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(ref rtc) = self.rtc {
+            data.extend(rtc.footer());
+        }
+        data
+    }
+
+    fn save_changed(&self, saved: &[u8]) -> bool {
+        // The clock registers change every second while the time they were
+        // saved at lets them catch up on load, so only the RAM & registers
+        // set by the game need a new save.
+        let footer = if self.rtc.is_some() { FOOTER_SIZE } else { 0 };
+        saved.len() != self.ram.len() + footer
+            || saved[..self.ram.len()] != self.ram[..]
+            || self.rtc.as_ref().is_some_and(|rtc| rtc.written)
+    }
+
+    fn mark_saved(&mut self) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.written = false;
+        }
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        let len = load_ram(&mut self.ram, data);
+        let footer = &data[len..];
+        if let Some(ref mut rtc) = self.rtc {
+            if footer.len() == FOOTER_SIZE || footer.len() == SHORT_FOOTER_SIZE {
+                rtc.load_footer(footer);
+            }
+        }
+    }
+
+    fn rom(&self) -> &[u8] { &self.rom }
+
+    fn rom_mut(&mut self) -> &mut [u8] { &mut self.rom }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.rom_bank as usize }
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        match self.ram_bank {
+            0x00 ..= 0x07 => Some(self.ram_bank as usize),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::tests::{banked_rom, bank_at};
+
+    /// Returns an MBC3 with 8KB of RAM & a clock counting emulated cycles
+    fn mbc3() -> Mbc3 {
+        let mut mbc = Mbc3::new(banked_rom(0x80), 0x2000, true);
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc
+    }
+
+    fn write_rtc(mbc: &mut Mbc3, regs: [u8; 5]) {
+        for (i, &val) in regs.iter().enumerate() {
+            mbc.write_rom(0x4000, 0x08 + i as u8);
+            mbc.write_ram(0xA000, val);
+        }
+    }
+
+    fn read_rtc(mbc: &mut Mbc3) -> [u8; 5] {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        let mut regs = [0; 5];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            mbc.write_rom(0x4000, 0x08 + i as u8);
+            *reg = mbc.read_ram(0xA000);
+        }
+        regs
+    }
+
+    #[test]
+    fn rom_banking() {
+        let mut mbc = mbc3();
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x01);
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x20);
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x7F);
+    }
+
+    #[test]
+    fn clock_counts_and_latches() {
+        let mut mbc = mbc3();
+        write_rtc(&mut mbc, [59, 59, 23, 0xFF, 0x01]);
+        mbc.tick(CLOCK_RATE);
+        // day 511 rolls over into the carry bit
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0x00, 0x80]);
+
+        // Without latching the old values are read
+        mbc.tick(CLOCK_RATE);
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 0);
+        assert_eq!(read_rtc(&mut mbc)[0], 1);
+
+        // The halt bit stops the clock
+        write_rtc(&mut mbc, [10, 0, 0, 0, 0x40]);
+        mbc.tick(CLOCK_RATE);
+        assert_eq!(read_rtc(&mut mbc), [10, 0, 0, 0, 0x40]);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut mbc = mbc3();
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_ram(0xA123, 0x42);
+        write_rtc(&mut mbc, [12, 34, 5, 0x67, 0x01]);
+        let regs = read_rtc(&mut mbc);
+
+        let save = mbc.save();
+        assert_eq!(save.len(), 0x2000 + FOOTER_SIZE);
+        assert_eq!(save[0x2000..0x2004], [12, 0, 0, 0]);
+        assert_eq!(save[0x2000 + 16..0x2000 + 20], [0x01, 0, 0, 0]);
+
+        let mut loaded = mbc3();
+        loaded.load(&save);
+        assert_eq!(loaded.ram()[0x0123], 0x42);
+        assert_eq!(read_rtc(&mut loaded), regs);
+
+        // Older saves with a 32-bit time
+        let mut loaded = mbc3();
+        loaded.load(&save[..0x2000 + SHORT_FOOTER_SIZE]);
+        assert_eq!(read_rtc(&mut loaded), regs);
+    }
+
+    #[test]
+    fn save_changes_ignore_the_running_clock() {
+        let mut mbc = mbc3();
+        mbc.mark_saved();
+        let save = mbc.save();
+        mbc.tick(CLOCK_RATE * 2);
+        assert!(!mbc.save_changed(&save));
+
+        write_rtc(&mut mbc, [0, 0, 0, 0, 0x40]);
+        assert!(mbc.save_changed(&save));
+        let save = mbc.save();
+        mbc.mark_saved();
+        assert!(!mbc.save_changed(&save));
+
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_ram(0xA000, 0x01);
+        assert!(mbc.save_changed(&save));
+    }
+
+    #[test]
+    fn peek_and_poke_keep_the_clock_running() {
+        let mut mbc = mbc3();
+        mbc.write_rom(0x4000, 0x08);
+        mbc.tick(CLOCK_RATE - 1);
+        // unlike a write this doesn't restart the second
+        mbc.poke_ram(0xA000, 10);
+        mbc.tick(1);
+        assert_eq!(mbc.peek_ram(0xA000), 0);
+        assert_eq!(read_rtc(&mut mbc)[0], 11);
+        assert!(!mbc.rtc.as_ref().unwrap().written);
+
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_rom(0x0000, 0x00);
+        mbc.poke_ram(0xA010, 0x42);
+        assert_eq!(mbc.read_ram(0xA010), 0xFF);
+        assert_eq!(mbc.peek_ram(0xA010), 0x42);
+    }
+}
@@ -0,0 +1,146 @@
+// MBC5 - up to 8MB of ROM and 128KB of RAM
+//
+// 0x0000 - 0x1FFF : RAM Enable (0x0A in the lower 4 bits enables the RAM)
+// 0x2000 - 0x2FFF : Lower 8 bits of the ROM Bank Number
+// 0x3000 - 0x3FFF : Bit 8 of the ROM Bank Number
+// 0x4000 - 0x5FFF : RAM Bank Number (0x00 - 0x0F)
+//
+// Unlike the older MBCs, bank 0 can also be mapped to [4000 -> 7FFF].
+// Rumble cartridges connect the motor to bit 3 of the RAM bank number, so
+// they only have 8 RAM banks.
+
+use cartridge::{Cartridge, load_ram, rom_byte, rom_bank_offset, ram_bank_offset};
+
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool, // motor is on
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    /// Returns the offset into the RAM of `addr`
+    fn ram_offset(&self, addr: u16) -> usize {
+        ram_bank_offset(&self.ram, self.ram_bank as usize) + (addr & 0x1FFF) as usize
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = Cartridge::rom_bank(self, addr);
+        rom_byte(&self.rom, rom_bank_offset(&self.rom, bank) + (addr & 0x3FFF) as usize)
+    }
+
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => { self.ram_enabled = val & 0x0F == 0x0A; },
+            0x2000 ..= 0x2FFF => { self.rom_bank = self.rom_bank & 0x100 | val as u16; },
+            0x3000 ..= 0x3FFF => { self.rom_bank = self.rom_bank & 0xFF | (val as u16 & 0x01) << 8; },
+            0x4000 ..= 0x5FFF if self.has_rumble => {
+                self.rumble = val & 0x08 != 0;
+                self.ram_bank = val & 0x07;
+            },
+            0x4000 ..= 0x5FFF => { self.ram_bank = val & 0x0F; },
+            _ => { },
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(addr) % self.ram.len()]
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(addr) % self.ram.len();
+        self.ram[offset] = val;
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn rom(&self) -> &[u8] { &self.rom }
+
+    fn rom_mut(&mut self) -> &mut [u8] { &mut self.rom }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { self.rom_bank as usize }
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        Some(self.ram_bank as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::tests::{banked_rom, bank_at};
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut mbc = Mbc5::new(banked_rom(0x200), 0, false);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x001);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x000);
+        mbc.write_rom(0x2000, 0x23);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x123);
+        mbc.write_rom(0x2FFF, 0xFF);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x1FF);
+        mbc.write_rom(0x3FFF, 0x00);
+        assert_eq!(bank_at(&mbc, 0x4000), 0x0FF);
+        assert_eq!(bank_at(&mbc, 0x0000), 0x000);
+    }
+
+    #[test]
+    fn ram_banks_and_rumble() {
+        let mut mbc = Mbc5::new(banked_rom(0x02), 0x20000, false);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0xA001, 0x42);
+        assert_eq!(mbc.ram()[0xF * 0x2000 + 1], 0x42);
+
+        // Bit 3 drives the motor instead of selecting RAM
+        let mut mbc = Mbc5::new(banked_rom(0x02), 0x8000, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x09);
+        assert!(mbc.rumble());
+        mbc.write_ram(0xA000, 0x42);
+        assert_eq!(mbc.ram()[0x2000], 0x42);
+        mbc.write_rom(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+}
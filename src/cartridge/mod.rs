@@ -0,0 +1,237 @@
+// Cartridges map their ROM to [0000 -> 7FFF] and optional external RAM to
+// [A000 -> BFFF]. Larger cartridges have a memory bank controller (MBC) which
+// switches the banks visible in those areas; it's controlled by writing to
+// the (read only) ROM area.
+
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+
+use header::{CartridgeHeader, CartridgeType};
+use rgb_error::RgbError;
+
+pub use self::mbc1::Mbc1;
+pub use self::mbc2::Mbc2;
+pub use self::mbc3::{Mbc3, RtcClock};
+pub use self::mbc5::Mbc5;
+
+/// Size of a ROM bank
+pub const ROM_BANK_SIZE: usize = 0x4000;
+/// Size of an external RAM bank
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// A cartridge as seen from the address bus
+pub trait Cartridge {
+    /// Reads a byte from the ROM area [0000 -> 7FFF]
+    fn read_rom(&self, addr: u16) -> u8;
+
+    /// Writes a byte to the ROM area [0000 -> 7FFF], which controls the MBC
+    fn write_rom(&mut self, addr: u16, val: u8);
+
+    /// Reads a byte from the external RAM area [A000 -> BFFF]
+    fn read_ram(&self, addr: u16) -> u8;
+
+    /// Writes a byte to the external RAM area [A000 -> BFFF]
+    fn write_ram(&mut self, addr: u16, val: u8);
+
+    /// Reads the external RAM area [A000 -> BFFF] for debugging, without side
+    /// effects and also while the RAM is disabled
+    fn peek_ram(&self, addr: u16) -> u8 {
+        match self.ram_bank().and_then(|bank| ram_offset(self.ram(), bank, addr)) {
+            Some(offset) => self.ram()[offset],
+            None => 0xFF,
+        }
+    }
+
+    /// Writes the external RAM area [A000 -> BFFF] for debugging, without side
+    /// effects and also while the RAM is disabled
+    fn poke_ram(&mut self, addr: u16, val: u8) {
+        if let Some(offset) = self.ram_bank().and_then(|bank| ram_offset(self.ram(), bank, addr)) {
+            self.ram_mut()[offset] = val;
+        }
+    }
+
+    /// Advances clocks on the cartridge by `cycles` clock cycles
+    fn tick(&mut self, _cycles: u32) { }
+
+    /// Selects the time source of a real time clock on the cartridge
+    fn set_rtc_clock(&mut self, _clock: RtcClock) { }
+
+    /// Returns if the rumble motor of the cartridge is on
+    fn rumble(&self) -> bool { false }
+
+    /// Returns the battery backed state in the format of a save file
+    fn save(&self) -> Vec<u8> { Vec::new() }
+
+    /// Returns if the battery backed state differs from `saved`, a save file
+    /// written earlier
+    fn save_changed(&self, saved: &[u8]) -> bool {
+        self.save() != saved
+    }
+
+    /// Called once the battery backed state has been written to a save file
+    fn mark_saved(&mut self) { }
+
+    /// Restores the battery backed state from a save file
+    fn load(&mut self, _data: &[u8]) { }
+
+    /// Returns the whole ROM
+    fn rom(&self) -> &[u8];
+
+    /// Returns the whole ROM mutably
+    fn rom_mut(&mut self) -> &mut [u8];
+
+    /// Returns the whole external RAM
+    fn ram(&self) -> &[u8];
+
+    /// Returns the whole external RAM mutably
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    /// Returns the ROM bank mapped at `addr` [0000 -> 7FFF]
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 { 0 } else { 1 }
+    }
+
+    /// Returns the RAM bank mapped to [A000 -> BFFF], also while the RAM is
+    /// disabled; None while something else (e.g. a clock register) is mapped.
+    fn ram_bank(&self) -> Option<usize> { Some(0) }
+}
+
+/// Creates the cartridge described by `header`
+pub fn from_header(rom: Vec<u8>, header: &CartridgeHeader) -> Result<Box<dyn Cartridge>, RgbError> {
+    let ram_size = header.ram_size;
+    Ok(match header.cartridge_type {
+        CartridgeType::RomOnly |
+        CartridgeType::RomRam |
+        CartridgeType::RomRamBattery => Box::new(RomOnly::new(rom, ram_size)),
+        CartridgeType::Mbc1 |
+        CartridgeType::Mbc1Ram |
+        CartridgeType::Mbc1RamBattery => Box::new(Mbc1::new(rom, ram_size)),
+        CartridgeType::Mbc2 |
+        CartridgeType::Mbc2Battery => Box::new(Mbc2::new(rom)),
+        CartridgeType::Mbc3 |
+        CartridgeType::Mbc3Ram |
+        CartridgeType::Mbc3RamBattery |
+        CartridgeType::Mbc3TimerBattery |
+        CartridgeType::Mbc3TimerRamBattery => {
+            Box::new(Mbc3::new(rom, ram_size, header.cartridge_type.has_timer()))
+        },
+        CartridgeType::Mbc5 |
+        CartridgeType::Mbc5Ram |
+        CartridgeType::Mbc5RamBattery |
+        CartridgeType::Mbc5Rumble |
+        CartridgeType::Mbc5RumbleRam |
+        CartridgeType::Mbc5RumbleRamBattery => {
+            Box::new(Mbc5::new(rom, ram_size, header.cartridge_type.has_rumble()))
+        },
+        other => return Err(RgbError::UnsupportedCartridge(other)),
+    })
+}
+
+/// Returns the byte at `offset` of `rom`; reads past the end return 0xFF
+pub fn rom_byte(rom: &[u8], offset: usize) -> u8 {
+    rom.get(offset).cloned().unwrap_or(0xFF)
+}
+
+/// Copies the start of a save file into `ram`; returns the bytes copied
+pub fn load_ram(ram: &mut [u8], data: &[u8]) -> usize {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+/// Returns the offset of ROM bank `bank`, wrapping around the banks present
+pub fn rom_bank_offset(rom: &[u8], bank: usize) -> usize {
+    let banks = (rom.len() / ROM_BANK_SIZE).max(1);
+    (bank % banks) * ROM_BANK_SIZE
+}
+
+/// Returns the offset of RAM bank `bank`, wrapping around the banks present
+pub fn ram_bank_offset(ram: &[u8], bank: usize) -> usize {
+    let banks = (ram.len() / RAM_BANK_SIZE).max(1);
+    (bank % banks) * RAM_BANK_SIZE
+}
+
+/// Returns the offset of `addr` [A000 -> BFFF] in RAM bank `bank`, wrapping
+/// around the RAM present
+pub fn ram_offset(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((ram_bank_offset(ram, bank) + (addr & 0x1FFF) as usize) % ram.len())
+}
+
+/// A 32KB cartridge without a MBC, optionally with up to 8KB of RAM
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size.min(RAM_BANK_SIZE)],
+        }
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn read_rom(&self, addr: u16) -> u8 {
+        rom_byte(&self.rom, addr as usize)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _val: u8) { }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.ram.get((addr & 0x1FFF) as usize).cloned().unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if let Some(byte) = self.ram.get_mut((addr & 0x1FFF) as usize) {
+            *byte = val;
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn rom(&self) -> &[u8] { &self.rom }
+
+    fn rom_mut(&mut self) -> &mut [u8] { &mut self.rom }
+
+    fn ram(&self) -> &[u8] { &self.ram }
+
+    fn ram_mut(&mut self) -> &mut [u8] { &mut self.ram }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Cartridge, ROM_BANK_SIZE};
+
+    /// Returns a ROM of `banks` banks which start with their bank number,
+    /// as a little endian word
+    pub fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+            rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    /// Returns the number of the ROM bank mapped to [0000 -> 3FFF] or
+    /// [4000 -> 7FFF], whichever `addr` is in
+    pub fn bank_at(cart: &dyn Cartridge, addr: u16) -> usize {
+        let start = addr & 0x4000;
+        let bank = cart.read_rom(start) as usize | ((cart.read_rom(start + 1) as usize) << 8);
+        assert_eq!(bank, cart.rom_bank(addr));
+        bank
+    }
+}
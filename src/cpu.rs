@@ -4,7 +4,8 @@
 
 use std::cell::RefCell;
 use std::rc::Rc;
-use mmu::MMU;
+use mmu::MemoryBus;
+use interrupt::Interrupt;
 
 // The Nintendo documents describe the CPU & instructions speed in machine
 // cycles; while this document will be describing them in clock cycles. Here is
@@ -17,7 +18,7 @@ use mmu::MMU;
 
 // Register types
 // NOTE: C0 is really (0xFF00 + C) == (C)
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Register {
     // 8-bit registers
     A, B, D, H, F, C, C0, E, L,
@@ -30,9 +31,10 @@ pub enum Register {
 
 
 // Instruction List
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Instruction {
     ExtInstr, // Extended Instruction Set use byte (0xCB)
+    ILLEGAL(u8), // Opcodes without an instruction
     LD   (Register, Register),
     LDD  (Register, Register),
     LDI  (Register, Register),
@@ -50,6 +52,8 @@ pub enum Instruction {
     CP   (Register),
     INC  (Register),
     DEC  (Register),
+    INC16 (Register),
+    DEC16 (Register),
     DAA,
     CPL,
     CCF,
@@ -81,10 +85,10 @@ pub enum Instruction {
     CALLC  (Register),
     RST (u8),
     RET,
-    RETNZ (Register),
-    RETZ  (Register),
-    RETNC (Register),
-    RETC  (Register),
+    RETNZ,
+    RETZ,
+    RETNC,
+    RETC,
     RETI,
     // extended instructions
     SWAP (Register),
@@ -104,6 +108,10 @@ pub enum Instruction {
 pub struct CPU {
     halt:    bool,
     stop:    bool,
+    locked:  bool, // an illegal opcode hard locked the CPU
+    ime:     bool, // Interrupt master enable
+    ime_pending: bool, // `EI` takes effect after the next instruction
+    halt_bug: bool, // the next `p_fetch` doesn't increment the PC
     clock_m: f32,
     clock_t: f32,
     reg_a:   u8,
@@ -126,6 +134,9 @@ impl CPU {
     pub fn new() -> CPU {
         CPU {
             halt:    false, stop:    false,
+            locked:  false,
+            ime:     false, ime_pending: false,
+            halt_bug: false,
             clock_m: 0.0,   clock_t: 0.0,
             reg_a:   0,     reg_b:   0,
             reg_c:   0,     reg_d:   0,
@@ -195,13 +206,13 @@ impl CPU {
             0x5D => I::LD(Reg::E, Reg::L),
             0x5E => I::LD(Reg::E, Reg::HL),
             // -- (H -> n)
-            0x60 => I::LD(Reg::D, Reg::B),
-            0x61 => I::LD(Reg::D, Reg::C),
-            0x62 => I::LD(Reg::D, Reg::D),
-            0x63 => I::LD(Reg::D, Reg::E),
-            0x64 => I::LD(Reg::D, Reg::H),
-            0x65 => I::LD(Reg::D, Reg::L),
-            0x66 => I::LD(Reg::D, Reg::HL),
+            0x60 => I::LD(Reg::H, Reg::B),
+            0x61 => I::LD(Reg::H, Reg::C),
+            0x62 => I::LD(Reg::H, Reg::D),
+            0x63 => I::LD(Reg::H, Reg::E),
+            0x64 => I::LD(Reg::H, Reg::H),
+            0x65 => I::LD(Reg::H, Reg::L),
+            0x66 => I::LD(Reg::H, Reg::HL),
             // -- (L -> n)
             0x68 => I::LD(Reg::L, Reg::B),
             0x69 => I::LD(Reg::L, Reg::C),
@@ -234,7 +245,7 @@ impl CPU {
             0x02 => I::LD(Reg::BC, Reg::A),
             0x12 => I::LD(Reg::DE, Reg::A),
             0x77 => I::LD(Reg::HL, Reg::A),
-            0xEA => I::LD(BYTE, Reg::A),
+            0xEA => I::LD(WORD, Reg::A), // LS byte first
             // LD A, (C) : put value at 0xFF00 + Reg::C into A
             //             same as LD A, (0xFF00 + C)
             0xF2 => I::LD(Reg::A, Reg::C0),
@@ -322,7 +333,7 @@ impl CPU {
             0x9C => I::SBC(Reg::A, Reg::H),
             0x9D => I::SBC(Reg::A, Reg::L),
             0x9E => I::SBC(Reg::A, Reg::HL),
-            //0x__ => I::ADC(Reg::A, BYTE), // XXX: does this opcode exist?
+            0xDE => I::SBC(Reg::A, BYTE),
             // AND n : Logically AND `n` with A, Result in A
             0xA7 => I::AND(Reg::A),
             0xA0 => I::AND(Reg::B),
@@ -388,17 +399,17 @@ impl CPU {
             0x29 => I::ADD(Reg::HL, Reg::HL),
             0x39 => I::ADD(Reg::HL, Reg::SP),
             // ADD SP, n : Add n to SP
-            0xE8 => I::ADD(Reg::SP, WORD),
+            0xE8 => I::ADD(Reg::SP, BYTE), // NOTE: n is one byte signed value
             // INC nn : Increment register nn
-            0x03 => I::INC(Reg::BC),
-            0x13 => I::INC(Reg::DE),
-            0x23 => I::INC(Reg::HL),
-            0x33 => I::INC(Reg::SP),
+            0x03 => I::INC16(Reg::BC),
+            0x13 => I::INC16(Reg::DE),
+            0x23 => I::INC16(Reg::HL),
+            0x33 => I::INC16(Reg::SP),
             // DEC nn : decrement register nn
-            0x0B => I::DEC(Reg::BC),
-            0x1B => I::DEC(Reg::DE),
-            0x2B => I::DEC(Reg::HL),
-            0x3B => I::DEC(Reg::SP),
+            0x0B => I::DEC16(Reg::BC),
+            0x1B => I::DEC16(Reg::DE),
+            0x2B => I::DEC16(Reg::HL),
+            0x3B => I::DEC16(Reg::SP),
             // Misc. ----------------------------------------------------------
             // DAA : Decimal adjust register A
             //     : Adjusts value in register A so that the correct Binary
@@ -471,19 +482,17 @@ impl CPU {
             // RET : pop two bytes from the stack and jump to that address
             0xC9 => I::RET,
             // RET cc : return if following condition is true
-            0xC0 => I::RETNZ(WORD), // if Z flag is low
-            0xC8 => I::RETZ(WORD),  // if Z flag is high
-            0xD0 => I::RETNC(WORD), // if C flag is low
-            0xD8 => I::RETC(WORD),  // if C flag is high
+            0xC0 => I::RETNZ, // if Z flag is low
+            0xC8 => I::RETZ,  // if Z flag is high
+            0xD0 => I::RETNC, // if C flag is low
+            0xD8 => I::RETC,  // if C flag is high
             // RETI : pop two bytes from stack and jump to that address
             //      : while also enabling interrupts.
             0xD9 => I::RETI,
             // ExtInstr : Marker that the next byte is an extended instruction
             0xCB => I::ExtInstr,
-            _ => {
-                println!("Decoded invalid instruction: 0x{:X}", byte);
-                I::NOP
-            },
+            // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC & 0xFD
+            _ => I::ILLEGAL(byte),
         }
     }
 
@@ -786,35 +795,169 @@ impl CPU {
             0xFC => I::SET(7, Reg::H),
             0xFD => I::SET(7, Reg::L),
             0xFE => I::SET(7, Reg::HL),
-            _ => {
-                println!("Decoded invalid extended instruction: 0x{:X}", byte);
-                I::NOP
-            },
         }
     }
 
     /// Returns the next instruction and increments the program counter
-    pub fn p_fetch(&mut self, mmu: &MMU) -> u8 {
-        let byte = mmu.rb(self, self.reg_pc);
-        self.reg_pc += 1;
+    /// After the HALT bug has been triggered the program counter isn't
+    /// incremented once, so the same byte is read twice.
+    pub fn p_fetch<B: MemoryBus>(&mut self, mmu: &mut B) -> u8 {
+        let byte = mmu.rb(self.reg_pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.reg_pc = self.reg_pc.wrapping_add(1);
+        }
         byte
     }
 
     /// Returns the value of the CPU's program counter
     pub fn get_pc(&self) -> u16 { self.reg_pc }
 
+    /// Sets the program counter
+    pub fn set_pc(&mut self, pc: u16) { self.reg_pc = pc; }
+
     /// Resets processor state
-    fn reset(&mut self) {
+    pub fn reset(&mut self) {
         self.clock_m = 0.0; self.clock_t = 0.0;
         self.reg_a =  0; self.reg_b =  0; self.reg_c =  0;
         self.reg_d =  0; self.reg_e =  0; self.reg_h =  0;
         self.reg_l =  0; self.reg_f =  0; self.reg_pc = 0;
         self.reg_sp = 0;
         self.halt = false; self.stop = false;
+        self.locked = false;
+        self.ime = false; self.ime_pending = false;
+        self.halt_bug = false;
         self.zero_flag = false; self.sub_flag = false;
         self.carry_flag = false; self.half_carry_flag = false;
     }
 
+    /*
+     * REGISTER ACCESS
+     */
+
+    /// Returns the value of the zero flag
+    pub fn zero_flag(&self) -> bool { self.zero_flag }
+
+    /// Returns the value of the carry flag
+    pub fn carry_flag(&self) -> bool { self.carry_flag }
+
+    /// Packs the CPU flags into the layout of the `F` register
+    /// bit: 7 - Z, 6 - N, 5 - H, 4 - C; the lower nibble always reads zero.
+    fn get_f(&self) -> u8 {
+        (self.zero_flag as u8) << 7 | (self.sub_flag as u8) << 6 |
+        (self.half_carry_flag as u8) << 5 | (self.carry_flag as u8) << 4
+    }
+
+    /// Unpacks a value of the `F` register into the CPU flags
+    fn set_f(&mut self, val: u8) {
+        self.zero_flag = val & 0x80 != 0;
+        self.sub_flag = val & 0x40 != 0;
+        self.half_carry_flag = val & 0x20 != 0;
+        self.carry_flag = val & 0x10 != 0;
+    }
+
+    /// Returns the value of the `HL` register pair
+    fn hl(&self) -> u16 {
+        (self.reg_h as u16) << 8 | self.reg_l as u16
+    }
+
+    /// Returns the value of a 16-bit register (`AF`, `BC`, `DE`, `HL`, `SP`)
+    pub fn get_r16(&self, reg: Register) -> u16 {
+        match reg {
+            Register::AF => (self.reg_a as u16) << 8 | self.get_f() as u16,
+            Register::BC => (self.reg_b as u16) << 8 | self.reg_c as u16,
+            Register::DE => (self.reg_d as u16) << 8 | self.reg_e as u16,
+            Register::HL => self.hl(),
+            Register::SP => self.reg_sp,
+            _ => unreachable!()
+        }
+    }
+
+    /// Sets the value of a 16-bit register (`AF`, `BC`, `DE`, `HL`, `SP`)
+    pub fn set_r16(&mut self, reg: Register, val: u16) {
+        let hi = (val >> 8) as u8;
+        let lo = val as u8;
+        match reg {
+            Register::AF => { self.reg_a = hi; self.set_f(lo); },
+            Register::BC => { self.reg_b = hi; self.reg_c = lo; },
+            Register::DE => { self.reg_d = hi; self.reg_e = lo; },
+            Register::HL => { self.reg_h = hi; self.reg_l = lo; },
+            Register::SP => { self.reg_sp = val; },
+            _ => unreachable!()
+        }
+    }
+
+    /// Reads an 8-bit operand
+    /// `HL`, `BC` & `DE` are read indirectly, `C0` is `(0xFF00 + C)` and
+    /// `BYTE` is the immediate value `data`.
+    pub fn read_r8<B: MemoryBus>(&self, mmu: &mut B, reg: Register, data: u8) -> u8 {
+        match reg {
+            Register::A => self.reg_a,
+            Register::B => self.reg_b,
+            Register::C => self.reg_c,
+            Register::D => self.reg_d,
+            Register::E => self.reg_e,
+            Register::H => self.reg_h,
+            Register::L => self.reg_l,
+            Register::HL => mmu.rb(self.hl()),
+            Register::BC => mmu.rb(self.get_r16(Register::BC)),
+            Register::DE => mmu.rb(self.get_r16(Register::DE)),
+            Register::C0 => mmu.rb(0xFF00 + self.reg_c as u16),
+            Register::BYTE => data,
+            _ => unreachable!()
+        }
+    }
+
+    /// Writes an 8-bit operand
+    /// `HL`, `BC` & `DE` are written indirectly and `C0` is `(0xFF00 + C)`.
+    pub fn write_r8<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register, val: u8) {
+        match reg {
+            Register::A => { self.reg_a = val; },
+            Register::B => { self.reg_b = val; },
+            Register::C => { self.reg_c = val; },
+            Register::D => { self.reg_d = val; },
+            Register::E => { self.reg_e = val; },
+            Register::H => { self.reg_h = val; },
+            Register::L => { self.reg_l = val; },
+            Register::HL => { mmu.wb(self.hl(), val); },
+            Register::BC => { mmu.wb(self.get_r16(Register::BC), val); },
+            Register::DE => { mmu.wb(self.get_r16(Register::DE), val); },
+            Register::C0 => { mmu.wb(0xFF00 + self.reg_c as u16, val); },
+            _ => unreachable!()
+        }
+    }
+
+    /// Pushes a word onto the stack; high byte first, after an internal
+    /// M-cycle which decrements `SP`.
+    fn push_word<B: MemoryBus>(&mut self, mmu: &mut B, val: u16) {
+        mmu.idle();
+        self.reg_sp = self.reg_sp.wrapping_sub(1);
+        mmu.wb(self.reg_sp, (val >> 8) as u8);
+        self.reg_sp = self.reg_sp.wrapping_sub(1);
+        mmu.wb(self.reg_sp, val as u8);
+    }
+
+    /// Pops a word off of the stack; low byte first.
+    fn pop_word<B: MemoryBus>(&mut self, mmu: &mut B) -> u16 {
+        let val = mmu.rw(self.reg_sp);
+        self.reg_sp = self.reg_sp.wrapping_add(2);
+        val
+    }
+
+    /// Returns if the condition of a conditional jump, call or return
+    /// instruction is met; unconditional instructions always return `true`.
+    pub fn condition(&self, inst: Instruction) -> bool {
+        use self::Instruction as I;
+        match inst {
+            I::JPNZ(_) | I::JRNZ(_) | I::CALLNZ(_) | I::RETNZ => !self.zero_flag,
+            I::JPZ(_)  | I::JRZ(_)  | I::CALLZ(_)  | I::RETZ  => self.zero_flag,
+            I::JPNC(_) | I::JRNC(_) | I::CALLNC(_) | I::RETNC => !self.carry_flag,
+            I::JPC(_)  | I::JRC(_)  | I::CALLC(_)  | I::RETC  => self.carry_flag,
+            _ => true,
+        }
+    }
+
     /*
      * INSTRUCTIONS
      */
@@ -841,54 +984,455 @@ impl CPU {
         }
     }
 
+    /// Load( Register, Register )
+    /// Put the value of the source into the destination. Covers the 8-bit
+    /// register, indirect & immediate loads as well as `LD SP, HL`,
+    /// `LD A, (nn)`, `LD (nn), A` and `LD (nn), SP`.
+    pub fn ld<B: MemoryBus>(&mut self, mmu: &mut B, dst: Register, src: Register, data: u16) {
+        match (dst, src) {
+            (Register::SP, Register::HL) => { self.reg_sp = self.hl(); },
+            (Register::A, Register::WORD) => { self.reg_a = mmu.rb(data); },
+            (reg, Register::WORD) => { self.ld_r_w(reg, data); },
+            (Register::WORD, Register::SP) => {
+                mmu.ww(data, self.reg_sp);
+            },
+            (Register::WORD, reg) => {
+                let val = self.read_r8(mmu, reg, 0);
+                mmu.wb(data, val);
+            },
+            (dst, src) => {
+                let val = self.read_r8(mmu, src, data as u8);
+                self.write_r8(mmu, dst, val);
+            },
+        }
+    }
+
     /// LoadDecrement( (HL), A )
     /// Put `A` into the memory at address `(HL)`, decrement `HL`
     /// LoadDecrement( A, (HL) )
-    /// Put value at address `(HL)` into `A`, decremnt `HL`
-    pub fn ldd_r_r(&mut self, mmu: &mut MMU, r_a: Register, r_b: Register) {
-        match r_a {
-            Register::HL => {
-                let h:u16 = self.reg_h as u16;
-                let l:u16 = self.reg_l as u16;
-                let addr:u16 = ( h << 8 ) + l;
-                mmu.wb(addr, self.reg_a);
-            },
-            Register::A => {
-                let addr:u16 = ( self.reg_h as u16 ) << 8
-                               + self.reg_l as u16;
-                self.reg_a = mmu.rb(self, addr);
-            },
-            _ => { unreachable!(); }
+    /// Put value at address `(HL)` into `A`, decrement `HL`
+    pub fn ldd_r_r<B: MemoryBus>(&mut self, mmu: &mut B, r_a: Register, r_b: Register) {
+        let val = self.read_r8(mmu, r_b, 0);
+        self.write_r8(mmu, r_a, val);
+        let hl = self.hl().wrapping_sub(1);
+        self.set_r16(Register::HL, hl);
+    }
+
+    /// LoadIncrement( (HL), A )
+    /// Put `A` into the memory at address `(HL)`, increment `HL`
+    /// LoadIncrement( A, (HL) )
+    /// Put value at address `(HL)` into `A`, increment `HL`
+    pub fn ldi_r_r<B: MemoryBus>(&mut self, mmu: &mut B, r_a: Register, r_b: Register) {
+        let val = self.read_r8(mmu, r_b, 0);
+        self.write_r8(mmu, r_a, val);
+        let hl = self.hl().wrapping_add(1);
+        self.set_r16(Register::HL, hl);
+    }
+
+    /// LoadHigh( (n), A )
+    /// Put `A` into memory at address `0xFF00 + n`
+    /// LoadHigh( A, (n) )
+    /// Put value at address `0xFF00 + n` into `A`
+    pub fn ldh<B: MemoryBus>(&mut self, mmu: &mut B, dst: Register, offset: u8) {
+        let addr = 0xFF00 + offset as u16;
+        match dst {
+            Register::BYTE => { mmu.wb(addr, self.reg_a); },
+            Register::A => { self.reg_a = mmu.rb(addr); },
+            _ => unreachable!()
         }
     }
 
-    /// XOR( Register )
-    /// Locgical exclusive OR `n` with register `A`, result in register `A`
+    /// Adds the signed byte `offset` to `SP` and returns the result
+    /// flags: Z - Reset
+    ///        N - Reset
+    ///        H - Set if carry from bit 3
+    ///        C - Set if carry from bit 7
+    fn sp_offset(&mut self, offset: u8) -> u16 {
+        let sp = self.reg_sp;
+        let val = offset as u16;
+        self.zero_flag = false;
+        self.sub_flag = false;
+        self.half_carry_flag = (sp & 0x0F) + (val & 0x0F) > 0x0F;
+        self.carry_flag = (sp & 0xFF) + (val & 0xFF) > 0xFF;
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    /// LDHL( SP, n )
+    /// Put `SP + n` into `HL`, where `n` is a signed byte
+    pub fn ldhl(&mut self, offset: u8) {
+        let val = self.sp_offset(offset);
+        self.set_r16(Register::HL, val);
+    }
+
+    /// ADD( SP, n )
+    /// Add the signed byte `n` to `SP`
+    pub fn add_sp(&mut self, offset: u8) {
+        self.reg_sp = self.sp_offset(offset);
+    }
+
+    /// PUSH( Register )
+    /// Push the register pair onto the stack
+    pub fn push<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.get_r16(reg);
+        self.push_word(mmu, val);
+    }
+
+    /// POP( Register )
+    /// Pop two bytes off of the stack into the register pair
+    pub fn pop<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.pop_word(mmu);
+        self.set_r16(reg, val);
+    }
+
+    /// ADD( A, n )
+    /// Add `n` to `A`
+    /// flags: Z - Set if result is zero
+    ///        N - Reset
+    ///        H - Set if carry from bit 3
+    ///        C - Set if carry from bit 7
+    pub fn add_a(&mut self, val: u8) {
+        let a = self.reg_a;
+        let res = a as u16 + val as u16;
+        self.reg_a = res as u8;
+        self.zero_flag = self.reg_a == 0;
+        self.sub_flag = false;
+        self.half_carry_flag = (a & 0x0F) + (val & 0x0F) > 0x0F;
+        self.carry_flag = res > 0xFF;
+    }
+
+    /// ADC( A, n )
+    /// Add `n` + carry flag to `A`
+    /// flags: Z - Set if result is zero
+    ///        N - Reset
+    ///        H - Set if carry from bit 3
+    ///        C - Set if carry from bit 7
+    pub fn adc_a(&mut self, val: u8) {
+        let a = self.reg_a;
+        let carry = self.carry_flag as u8;
+        let res = a as u16 + val as u16 + carry as u16;
+        self.reg_a = res as u8;
+        self.zero_flag = self.reg_a == 0;
+        self.sub_flag = false;
+        self.half_carry_flag = (a & 0x0F) + (val & 0x0F) + carry > 0x0F;
+        self.carry_flag = res > 0xFF;
+    }
+
+    /// SUB( A, n )
+    /// Subtract `n` from `A`
+    /// flags: Z - Set if result is zero
+    ///        N - Set
+    ///        H - Set if no borrow from bit 4
+    ///        C - Set if no borrow
+    pub fn sub_a(&mut self, val: u8) {
+        self.cp_a(val);
+        self.reg_a = self.reg_a.wrapping_sub(val);
+    }
+
+    /// SBC( A, n )
+    /// Subtract `n` + carry flag from `A`
+    /// flags: Z - Set if result is zero
+    ///        N - Set
+    ///        H - Set if no borrow from bit 4
+    ///        C - Set if no borrow
+    pub fn sbc_a(&mut self, val: u8) {
+        let a = self.reg_a;
+        let carry = self.carry_flag as u8;
+        self.reg_a = a.wrapping_sub(val).wrapping_sub(carry);
+        self.zero_flag = self.reg_a == 0;
+        self.sub_flag = true;
+        self.half_carry_flag = (a & 0x0F) < (val & 0x0F) + carry;
+        self.carry_flag = (a as u16) < val as u16 + carry as u16;
+    }
+
+    /// AND( n )
+    /// Logically AND `n` with register `A`, result in register `A`
+    /// flags: Z - Set if result is zero
+    ///        N - Reset
+    ///        H - Set
+    ///        C - Reset
+    pub fn and_a(&mut self, val: u8) {
+        self.reg_a &= val;
+        self.zero_flag = self.reg_a == 0;
+        self.sub_flag = false;
+        self.half_carry_flag = true;
+        self.carry_flag = false;
+    }
+
+    /// OR( n )
+    /// Logically OR `n` with register `A`, result in register `A`
     /// flags: Z - Set if result is zero
     ///        N - Reset
     ///        H - Reset
     ///        C - Reset
-    pub fn xor_r(&mut self, mmu: &mut MMU, reg: Register) {
-        match reg {
-            Register::A => { self.reg_a ^= self.reg_a; }
-            Register::B => { self.reg_a ^= self.reg_b; }
-            Register::C => { self.reg_a ^= self.reg_c; }
-            Register::D => { self.reg_a ^= self.reg_d; }
-            Register::E => { self.reg_a ^= self.reg_e; }
-            Register::H => { self.reg_a ^= self.reg_h; }
-            Register::L => { self.reg_a ^= self.reg_l; }
-            Register::HL => { // comp against value at (HL)
-                let h:u16 = self.reg_h as u16;
-                let l:u16 = self.reg_l as u16;
-                let addr:u16 = ( h << 8 ) + l;
-                self.reg_a ^= mmu.rb(self, addr);
+    pub fn or_a(&mut self, val: u8) {
+        self.reg_a |= val;
+        self.zero_flag = self.reg_a == 0;
+        self.sub_flag = false;
+        self.half_carry_flag = false;
+        self.carry_flag = false;
+    }
+
+    /// XOR( n )
+    /// Logical exclusive OR `n` with register `A`, result in register `A`
+    /// flags: Z - Set if result is zero
+    ///        N - Reset
+    ///        H - Reset
+    ///        C - Reset
+    pub fn xor_a(&mut self, val: u8) {
+        self.reg_a ^= val;
+        self.zero_flag = self.reg_a == 0;
+        self.sub_flag = false;
+        self.half_carry_flag = false;
+        self.carry_flag = false;
+    }
+
+    /// CP( n )
+    /// Compare `A` with `n`; this is a subtraction where the result is
+    /// thrown away.
+    /// flags: Z - Set if `A` == `n`
+    ///        N - Set
+    ///        H - Set if no borrow from bit 4
+    ///        C - Set if `A` < `n`
+    pub fn cp_a(&mut self, val: u8) {
+        let a = self.reg_a;
+        self.zero_flag = a == val;
+        self.sub_flag = true;
+        self.half_carry_flag = (a & 0x0F) < (val & 0x0F);
+        self.carry_flag = a < val;
+    }
+
+    /// INC( Register )
+    /// Increment the 8-bit register or `(HL)`
+    /// flags: Z - Set if result is zero
+    ///        N - Reset
+    ///        H - Set if carry from bit 3
+    ///        C - Not affected
+    pub fn inc<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        let res = val.wrapping_add(1);
+        self.write_r8(mmu, reg, res);
+        self.zero_flag = res == 0;
+        self.sub_flag = false;
+        self.half_carry_flag = val & 0x0F == 0x0F;
+    }
+
+    /// DEC( Register )
+    /// Decrement the 8-bit register or `(HL)`
+    /// flags: Z - Set if result is zero
+    ///        N - Set
+    ///        H - Set if no borrow from bit 4
+    ///        C - Not affected
+    pub fn dec<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        let res = val.wrapping_sub(1);
+        self.write_r8(mmu, reg, res);
+        self.zero_flag = res == 0;
+        self.sub_flag = true;
+        self.half_carry_flag = val & 0x0F == 0x00;
+    }
+
+    /// INC( Register ) [16-bit]
+    /// Increment the register pair; no flags are affected
+    pub fn inc16(&mut self, reg: Register) {
+        let val = self.get_r16(reg).wrapping_add(1);
+        self.set_r16(reg, val);
+    }
+
+    /// DEC( Register ) [16-bit]
+    /// Decrement the register pair; no flags are affected
+    pub fn dec16(&mut self, reg: Register) {
+        let val = self.get_r16(reg).wrapping_sub(1);
+        self.set_r16(reg, val);
+    }
+
+    /// ADD( HL, Register )
+    /// Add the register pair to `HL`
+    /// flags: Z - Not affected
+    ///        N - Reset
+    ///        H - Set if carry from bit 11
+    ///        C - Set if carry from bit 15
+    pub fn add_hl(&mut self, reg: Register) {
+        let hl = self.hl();
+        let val = self.get_r16(reg);
+        let res = hl as u32 + val as u32;
+        self.set_r16(Register::HL, res as u16);
+        self.sub_flag = false;
+        self.half_carry_flag = (hl & 0x0FFF) + (val & 0x0FFF) > 0x0FFF;
+        self.carry_flag = res > 0xFFFF;
+    }
+
+    /// DAA
+    /// Decimal adjust register `A` so that the result of the previous
+    /// addition or subtraction is in Binary Coded Decimal (BCD).
+    /// flags: Z - Set if register `A` is zero
+    ///        N - Not affected
+    ///        H - Reset
+    ///        C - Set or reset according to operation
+    pub fn daa(&mut self) {
+        let mut adjust = 0u8;
+        let mut carry = self.carry_flag;
+        if !self.sub_flag {
+            if carry || self.reg_a > 0x99 {
+                adjust |= 0x60;
+                carry = true;
             }
-            _ => unreachable!()
+            if self.half_carry_flag || (self.reg_a & 0x0F) > 0x09 {
+                adjust |= 0x06;
+            }
+            self.reg_a = self.reg_a.wrapping_add(adjust);
+        } else {
+            if carry { adjust |= 0x60; }
+            if self.half_carry_flag { adjust |= 0x06; }
+            self.reg_a = self.reg_a.wrapping_sub(adjust);
         }
-        if self.reg_a == 0 { self.zero_flag = true; }
+        self.zero_flag = self.reg_a == 0;
+        self.half_carry_flag = false;
+        self.carry_flag = carry;
+    }
+
+    /// CPL
+    /// Complement register `A` (flip all bits)
+    /// flags: N - Set
+    ///        H - Set
+    pub fn cpl(&mut self) {
+        self.reg_a = !self.reg_a;
+        self.sub_flag = true;
+        self.half_carry_flag = true;
+    }
+
+    /// CCF
+    /// Complement the carry flag
+    /// flags: N - Reset
+    ///        H - Reset
+    pub fn ccf(&mut self) {
+        self.carry_flag = !self.carry_flag;
         self.sub_flag = false;
         self.half_carry_flag = false;
-        self.carry_flag = false;
+    }
+
+    /// SCF
+    /// Set the carry flag
+    /// flags: N - Reset
+    ///        H - Reset
+    pub fn scf(&mut self) {
+        self.carry_flag = true;
+        self.sub_flag = false;
+        self.half_carry_flag = false;
+    }
+
+    /// Sets the flags after one of the accumulator rotates
+    /// flags: Z - Reset
+    ///        N - Reset
+    ///        H - Reset
+    ///        C - Contains the bit shifted out
+    fn rotate_a_flags(&mut self, carry: bool) {
+        self.zero_flag = false;
+        self.sub_flag = false;
+        self.half_carry_flag = false;
+        self.carry_flag = carry;
+    }
+
+    /// RLCA
+    /// Rotate `A` left; old bit 7 to carry flag
+    pub fn rlca(&mut self) {
+        let carry = self.reg_a & 0x80 != 0;
+        self.reg_a = self.reg_a.rotate_left(1);
+        self.rotate_a_flags(carry);
+    }
+
+    /// RLA
+    /// Rotate `A` left through the carry flag
+    pub fn rla(&mut self) {
+        let carry = self.reg_a & 0x80 != 0;
+        self.reg_a = self.reg_a << 1 | self.carry_flag as u8;
+        self.rotate_a_flags(carry);
+    }
+
+    /// RRCA
+    /// Rotate `A` right; old bit 0 to carry flag
+    pub fn rrca(&mut self) {
+        let carry = self.reg_a & 0x01 != 0;
+        self.reg_a = self.reg_a.rotate_right(1);
+        self.rotate_a_flags(carry);
+    }
+
+    /// RRA
+    /// Rotate `A` right through the carry flag
+    pub fn rra(&mut self) {
+        let carry = self.reg_a & 0x01 != 0;
+        self.reg_a = self.reg_a >> 1 | (self.carry_flag as u8) << 7;
+        self.rotate_a_flags(carry);
+    }
+
+    /// Stores the result of an extended rotate, shift or swap and sets flags
+    /// flags: Z - Set if result is zero
+    ///        N - Reset
+    ///        H - Reset
+    ///        C - Contains the bit shifted out
+    fn store_shifted<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register, res: u8, carry: bool) {
+        self.write_r8(mmu, reg, res);
+        self.zero_flag = res == 0;
+        self.sub_flag = false;
+        self.half_carry_flag = false;
+        self.carry_flag = carry;
+    }
+
+    /// SWAP( Register )
+    /// Swap the upper & lower nibbles of `n`
+    pub fn swap<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        self.store_shifted(mmu, reg, val.rotate_left(4), false);
+    }
+
+    /// RLC( Register )
+    /// Rotate `n` left; old bit 7 to carry flag
+    pub fn rlc<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        self.store_shifted(mmu, reg, val.rotate_left(1), val & 0x80 != 0);
+    }
+
+    /// RL( Register )
+    /// Rotate `n` left through the carry flag
+    pub fn rl<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        let res = val << 1 | self.carry_flag as u8;
+        self.store_shifted(mmu, reg, res, val & 0x80 != 0);
+    }
+
+    /// RRC( Register )
+    /// Rotate `n` right; old bit 0 to carry flag
+    pub fn rrc<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        self.store_shifted(mmu, reg, val.rotate_right(1), val & 0x01 != 0);
+    }
+
+    /// RR( Register )
+    /// Rotate `n` right through the carry flag
+    pub fn rr<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        let res = val >> 1 | (self.carry_flag as u8) << 7;
+        self.store_shifted(mmu, reg, res, val & 0x01 != 0);
+    }
+
+    /// SLA( Register )
+    /// Shift `n` left into carry; LSB of `n` set to 0
+    pub fn sla<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        self.store_shifted(mmu, reg, val << 1, val & 0x80 != 0);
+    }
+
+    /// SRA( Register )
+    /// Shift `n` right into carry; MSB of `n` doesn't change
+    pub fn sra<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        self.store_shifted(mmu, reg, val >> 1 | val & 0x80, val & 0x01 != 0);
+    }
+
+    /// SRL( Register )
+    /// Shift `n` right into carry; MSB of `n` set to 0
+    pub fn srl<B: MemoryBus>(&mut self, mmu: &mut B, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0);
+        self.store_shifted(mmu, reg, val >> 1, val & 0x01 != 0);
     }
 
     /// BIT( bit, Register )
@@ -897,62 +1441,65 @@ impl CPU {
     ///        N - Reset
     ///        H - Set
     ///        C - Not affected
-    pub fn bit_b_r(&mut self, mmu: &mut MMU, bit: u8, reg: Register) {
+    pub fn bit_b_r<B: MemoryBus>(&mut self, mmu: &mut B, bit: u8, reg: Register) {
         let mask = 0x1 << bit;
-        let val = match reg {
-            Register::A => { self.reg_a & mask }
-            Register::B => { self.reg_b & mask }
-            Register::C => { self.reg_c & mask }
-            Register::D => { self.reg_d & mask }
-            Register::E => { self.reg_e & mask }
-            Register::H => { self.reg_h & mask }
-            Register::L => { self.reg_l & mask }
-            // test value at address of (HL)
-            Register::HL => {
-                let h:u16 = self.reg_h as u16;
-                let l:u16 = self.reg_l as u16;
-                let addr:u16 = ( h << 8 ) + l;
-                mmu.rb(self, addr) & mask
-            }
-            _ => unreachable!()
-        };
-        if val == 0 {
-            self.zero_flag = true;
-        }
+        let val = self.read_r8(mmu, reg, 0) & mask;
+        self.zero_flag = val == 0;
         self.half_carry_flag = true;
         self.sub_flag = false;
     }
 
-    /// JPNZ ( Address )
-    /// Jump to `Address` if the CPU's `zero` flag is reset (false)
-    pub fn jpnz(&mut self, offset: u8) {
-        if self.zero_flag == false {
-            self.reg_pc += offset as u16;
-        }
+    /// SET( bit, Register )
+    /// Set bit `b` in register `r`; no flags are affected
+    pub fn set_b_r<B: MemoryBus>(&mut self, mmu: &mut B, bit: u8, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0) | 0x1 << bit;
+        self.write_r8(mmu, reg, val);
     }
 
-    /// JPZ ( Address )
-    /// Jump to `Address` if the CPU's `zero` flag is set (true)
-    pub fn jpz(&mut self, offset: u8) {
-        if self.zero_flag == true {
-            self.reg_pc += offset as u16;
-        }
+    /// RES( bit, Register )
+    /// Reset bit `b` in register `r`; no flags are affected
+    pub fn res_b_r<B: MemoryBus>(&mut self, mmu: &mut B, bit: u8, reg: Register) {
+        let val = self.read_r8(mmu, reg, 0) & !(0x1 << bit);
+        self.write_r8(mmu, reg, val);
     }
 
-    /// JPC ( Address )
-    /// Jump to `Address` if the CPU's `carry` flag is set (true)
-    pub fn jpc(&mut self, offset: u8) {
-        if self.carry_flag == true {
-            self.reg_pc += offset as u16;
-        }
+    /// JP ( Address )
+    /// Jump to `Address`
+    pub fn jp(&mut self, addr: u16) {
+        self.reg_pc = addr;
     }
 
-    /// JPNC ( Address )
-    /// Jump to `Address` if the CPU's `carry` flag is reset (false)
-    pub fn jpnc(&mut self, offset: u8) {
-        if self.carry_flag == false {
-            self.reg_pc += offset as u16;
-        }
+    /// JPHL
+    /// Jump to the address contained in `HL`
+    pub fn jp_hl(&mut self) {
+        self.reg_pc = self.hl();
+    }
+
+    /// JR ( Offset )
+    /// Add the signed byte `Offset` to the program counter and jump to it
+    pub fn jr(&mut self, offset: u8) {
+        self.reg_pc = self.reg_pc.wrapping_add(offset as i8 as u16);
+    }
+
+    /// CALL ( Address )
+    /// Push the address of the next instruction onto the stack and jump
+    /// to `Address`
+    pub fn call<B: MemoryBus>(&mut self, mmu: &mut B, addr: u16) {
+        let pc = self.reg_pc;
+        self.push_word(mmu, pc);
+        self.reg_pc = addr;
+    }
+
+    /// RST ( n )
+    /// Push the present address onto the stack and jump to `0x0000 + n`
+    pub fn rst<B: MemoryBus>(&mut self, mmu: &mut B, vector: u8) {
+        self.call(mmu, vector as u16);
+    }
+
+    /// RET
+    /// Pop two bytes from the stack and jump to that address
+    pub fn ret<B: MemoryBus>(&mut self, mmu: &mut B) {
+        self.reg_pc = self.pop_word(mmu);
     }
 
     /*
@@ -966,26 +1513,336 @@ impl CPU {
     }
 
     /// (HALT): halt the processor
-    pub fn halt(&mut self) {
-        unimplemented!();
+    /// The CPU idles until an enabled interrupt is requested (IE & IF != 0),
+    /// whether or not the interrupt master enable is set. If IME is clear
+    /// and an interrupt is already pending the CPU doesn't halt; instead the
+    /// byte after `HALT` is read twice (the DMG "HALT bug").
+    pub fn halt(&mut self, interrupt_pending: bool) {
+        if !self.ime && interrupt_pending {
+            self.halt_bug = true;
+        } else {
+            self.halt = true;
+        }
+    }
+
+    /// (STOP): stop the processor & LCD display
+    /// The CPU idles in low-power mode until one of the joypad lines goes
+    /// low.
+    pub fn stop(&mut self) {
+        self.stop = true;
+    }
+
+    /// Returns if the CPU is halted
+    pub fn is_halted(&self) -> bool { self.halt }
+
+    /// Returns if the CPU is in `STOP` mode
+    pub fn is_stopped(&self) -> bool { self.stop }
+
+    /// Leaves halt or stop mode
+    pub fn resume(&mut self) {
+        self.halt = false;
+        self.stop = false;
     }
 
     /// (DI): Disable Interrupts
-    /// This instruction disables interrupts 4 cycles after the `DI`
-    /// instruction is executed.
+    /// Clears the interrupt master enable; this also cancels a preceding `EI`
+    /// that hasn't taken effect yet.
     pub fn di(&mut self) {
-        unimplemented!();
+        self.ime = false;
+        self.ime_pending = false;
     }
 
     /// (EI): Enable Interrupts
-    /// Enable interrupts after the EI instruction is executed ( 4 cycles )
+    /// Sets the interrupt master enable after the instruction following the
+    /// `EI` has been executed.
     pub fn ei(&mut self) {
-        unimplemented!();
+        self.ime_pending = true;
+    }
+
+    /// (RETI): Return and enable interrupts
+    /// Same as `RET` but also sets the interrupt master enable immediately.
+    pub fn reti<B: MemoryBus>(&mut self, mmu: &mut B) {
+        self.ret(mmu);
+        self.ime = true;
+    }
+
+    /// Returns if the interrupt master enable is set
+    pub fn ime(&self) -> bool { self.ime }
+
+    /// Applies a pending `EI`. This is called once per instruction, after
+    /// interrupts have been checked but before the instruction is executed
+    /// so that the instruction following an `EI` can't be interrupted.
+    pub fn update_ime(&mut self) {
+        if self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+    }
+
+    /// Services the interrupt `int`, which has already been acknowledged
+    /// Clears the interrupt master enable, pushes the program counter onto the
+    /// stack and jumps to the interrupt's vector. Takes 20 cycles.
+    pub fn interrupt<B: MemoryBus>(&mut self, mmu: &mut B, int: Interrupt) {
+        self.ime = false;
+        mmu.idle();
+        let pc = self.reg_pc;
+        self.push_word(mmu, pc);
+        self.reg_pc = int.vector();
     }
 
     /// (UNDEF): Undefined operation
+    /// The illegal opcodes hard lock the CPU; it stops executing and doesn't
+    /// respond to interrupts while the rest of the machine keeps running.
     pub fn undef(&mut self) {
-        unreachable!();
+        self.locked = true;
+    }
+
+    /// Returns if an illegal opcode locked up the CPU
+    pub fn is_locked(&self) -> bool { self.locked }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interrupt::Interrupt;
+
+    /// 64KB of plain memory which counts the M-cycles spent on it
+    struct TestBus {
+        mem: Vec<u8>,
+        cycles: u32,
     }
 
+    impl TestBus {
+        fn new() -> TestBus {
+            TestBus { mem: vec![0; 0x10000], cycles: 0 }
+        }
+    }
+
+    impl MemoryBus for TestBus {
+        fn rb(&mut self, addr: u16) -> u8 {
+            self.cycles += 1;
+            self.mem[addr as usize]
+        }
+
+        fn wb(&mut self, addr: u16, val: u8) {
+            self.cycles += 1;
+            self.mem[addr as usize] = val;
+        }
+
+        fn idle(&mut self) {
+            self.cycles += 1;
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.mem[addr as usize]
+        }
+
+        fn poke(&mut self, addr: u16, val: u8) {
+            self.mem[addr as usize] = val;
+        }
+    }
+
+    /// Returns the flags as "ZNHC", with "-" for the cleared ones
+    fn flags(cpu: &CPU) -> String {
+        [(cpu.zero_flag, 'Z'), (cpu.sub_flag, 'N'), (cpu.half_carry_flag, 'H'), (cpu.carry_flag, 'C')]
+            .iter()
+            .map(|&(set, c)| if set { c } else { '-' })
+            .collect()
+    }
+
+    fn bcd(val: u8) -> u8 {
+        ((val / 10) << 4) | (val % 10)
+    }
+
+    #[test]
+    fn add_sets_all_flags() {
+        let mut cpu = CPU::new();
+        cpu.reg_a = 0x3A;
+        cpu.add_a(0xC6);
+        assert_eq!(cpu.reg_a, 0x00);
+        assert_eq!(flags(&cpu), "Z-HC");
+
+        cpu.reg_a = 0x3C;
+        cpu.carry_flag = true;
+        cpu.adc_a(0x12);
+        assert_eq!(cpu.reg_a, 0x4F);
+        assert_eq!(flags(&cpu), "----");
+    }
+
+    #[test]
+    fn sub_and_cp_borrow() {
+        let mut cpu = CPU::new();
+        cpu.reg_a = 0x3E;
+        cpu.sub_a(0x0F);
+        assert_eq!(cpu.reg_a, 0x2F);
+        assert_eq!(flags(&cpu), "-NH-");
+
+        cpu.cp_a(0x40);
+        assert_eq!(cpu.reg_a, 0x2F);
+        assert_eq!(flags(&cpu), "-N-C");
+
+        cpu.carry_flag = true;
+        cpu.sbc_a(0x2E);
+        assert_eq!(cpu.reg_a, 0x00);
+        assert_eq!(flags(&cpu), "ZN--");
+    }
+
+    #[test]
+    fn inc_dec_keep_carry() {
+        let mut bus = TestBus::new();
+        let mut cpu = CPU::new();
+        cpu.carry_flag = true;
+        cpu.reg_b = 0x0F;
+        cpu.inc(&mut bus, Register::B);
+        assert_eq!(cpu.reg_b, 0x10);
+        assert_eq!(flags(&cpu), "--HC");
+
+        cpu.reg_b = 0x01;
+        cpu.dec(&mut bus, Register::B);
+        assert_eq!(cpu.reg_b, 0x00);
+        assert_eq!(flags(&cpu), "ZN-C");
+    }
+
+    #[test]
+    fn sixteen_bit_adds() {
+        let mut cpu = CPU::new();
+        cpu.zero_flag = true;
+        cpu.set_r16(Register::HL, 0x8A23);
+        cpu.set_r16(Register::BC, 0x0605);
+        cpu.add_hl(Register::BC);
+        assert_eq!(cpu.get_r16(Register::HL), 0x9028);
+        assert_eq!(flags(&cpu), "Z-H-");
+        cpu.add_hl(Register::HL);
+        assert_eq!(cpu.get_r16(Register::HL), 0x2050);
+        assert_eq!(flags(&cpu), "Z--C");
+
+        // SP + n takes its flags from the low byte & clears Z
+        cpu.reg_sp = 0xFFF8;
+        cpu.ldhl(0x08);
+        assert_eq!(cpu.get_r16(Register::HL), 0x0000);
+        assert_eq!(flags(&cpu), "--HC");
+        cpu.add_sp(0xFE);
+        assert_eq!(cpu.reg_sp, 0xFFF6);
+        assert_eq!(flags(&cpu), "--HC");
+        cpu.add_sp(0x01);
+        assert_eq!(cpu.reg_sp, 0xFFF7);
+        assert_eq!(flags(&cpu), "----");
+    }
+
+    #[test]
+    fn low_bits_of_f_are_zero() {
+        let mut cpu = CPU::new();
+        cpu.set_r16(Register::AF, 0x12FF);
+        assert_eq!(cpu.get_r16(Register::AF), 0x12F0);
+        assert_eq!(flags(&cpu), "ZNHC");
+    }
+
+    #[test]
+    fn daa_after_bcd_add() {
+        let mut cpu = CPU::new();
+        for a in 0..100 {
+            for b in 0..100 {
+                cpu.reg_a = bcd(a);
+                cpu.add_a(bcd(b));
+                cpu.daa();
+                assert_eq!(cpu.reg_a, bcd((a + b) % 100), "{} + {}", a, b);
+                assert_eq!(cpu.carry_flag, a as u16 + b as u16 >= 100, "{} + {}", a, b);
+                assert_eq!(cpu.zero_flag, (a + b) % 100 == 0);
+                assert!(!cpu.half_carry_flag);
+            }
+        }
+    }
+
+    #[test]
+    fn daa_after_bcd_sub() {
+        let mut cpu = CPU::new();
+        for a in 0..100 {
+            for b in 0..100 {
+                cpu.reg_a = bcd(a);
+                cpu.sub_a(bcd(b));
+                cpu.daa();
+                assert_eq!(cpu.reg_a, bcd((a + 100 - b) % 100), "{} - {}", a, b);
+                assert_eq!(cpu.carry_flag, a < b, "{} - {}", a, b);
+                assert!(cpu.sub_flag);
+            }
+        }
+    }
+
+    #[test]
+    fn extended_rotates_and_shifts() {
+        let mut bus = TestBus::new();
+        let mut cpu = CPU::new();
+        cpu.reg_b = 0x85;
+        cpu.rlc(&mut bus, Register::B);
+        assert_eq!(cpu.reg_b, 0x0B);
+        assert_eq!(flags(&cpu), "---C");
+        cpu.rr(&mut bus, Register::B);
+        assert_eq!(cpu.reg_b, 0x85);
+        assert_eq!(flags(&cpu), "---C");
+        cpu.sra(&mut bus, Register::B);
+        assert_eq!(cpu.reg_b, 0xC2);
+        cpu.srl(&mut bus, Register::B);
+        assert_eq!(cpu.reg_b, 0x61);
+        assert_eq!(flags(&cpu), "----");
+        cpu.swap(&mut bus, Register::B);
+        assert_eq!(cpu.reg_b, 0x16);
+
+        // Unlike RLA the extended rotates set Z
+        cpu.reg_c = 0x80;
+        cpu.rl(&mut bus, Register::C);
+        assert_eq!(cpu.reg_c, 0x00);
+        assert_eq!(flags(&cpu), "Z--C");
+        cpu.sla(&mut bus, Register::C);
+        assert_eq!(flags(&cpu), "Z---");
+    }
+
+    #[test]
+    fn extended_bit_operations() {
+        let mut bus = TestBus::new();
+        let mut cpu = CPU::new();
+        cpu.set_r16(Register::HL, 0xC000);
+        cpu.carry_flag = true;
+        cpu.set_b_r(&mut bus, 7, Register::HL);
+        assert_eq!(bus.peek(0xC000), 0x80);
+        cpu.bit_b_r(&mut bus, 7, Register::HL);
+        assert_eq!(flags(&cpu), "--HC");
+        cpu.res_b_r(&mut bus, 7, Register::HL);
+        cpu.bit_b_r(&mut bus, 7, Register::HL);
+        assert_eq!(flags(&cpu), "Z-HC");
+        assert_eq!(bus.peek(0xC000), 0x00);
+    }
+
+    #[test]
+    fn stack_access_cycles() {
+        let mut bus = TestBus::new();
+        let mut cpu = CPU::new();
+        cpu.set_r16(Register::BC, 0xBEEF);
+        cpu.push(&mut bus, Register::BC);
+        assert_eq!(bus.cycles, 3);
+        assert_eq!(cpu.reg_sp, 0xFFFC);
+        assert_eq!(bus.peek(0xFFFD), 0xBE);
+        assert_eq!(bus.peek(0xFFFC), 0xEF);
+
+        bus.cycles = 0;
+        cpu.pop(&mut bus, Register::DE);
+        assert_eq!(bus.cycles, 2);
+        assert_eq!(cpu.get_r16(Register::DE), 0xBEEF);
+        assert_eq!(cpu.reg_sp, 0xFFFE);
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        let mut bus = TestBus::new();
+        let mut cpu = CPU::new();
+        cpu.ime = true;
+        cpu.set_pc(0x1234);
+        cpu.interrupt(&mut bus, Interrupt::Timer);
+        // 2 internal M-cycles & the push; the jump is the fifth
+        assert_eq!(bus.cycles, 4);
+        assert!(!cpu.ime());
+        assert_eq!(cpu.get_pc(), 0x0050);
+        assert_eq!(bus.peek(0xFFFD), 0x12);
+        assert_eq!(bus.peek(0xFFFC), 0x34);
+    }
 }
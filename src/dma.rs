@@ -0,0 +1,132 @@
+// OAM DMA copies 160 bytes from [XX00 -> XX9F] to OAM, where XX is the
+// value written to the DMA register (0xFF46). After a startup delay of one
+// M-cycle it copies one byte per M-cycle, so a transfer takes 160 M-cycles.
+//
+// While a transfer is running the DMA owns OAM (the CPU reads 0xFF from it)
+// and the bus it's reading from. The CPU reading from that bus gets the byte
+// the DMA is transferring instead. There are two buses:
+//   - the external bus: ROM [0000 -> 7FFF], external RAM & WRAM [A000 -> FDFF]
+//   - the video bus: VRAM [8000 -> 9FFF]
+// OAM, the I/O registers & HRAM aren't on either bus, which is why games run
+// their DMA routine from HRAM.
+//
+// Writing to 0xFF46 during a transfer restarts it; the old transfer keeps
+// running until the new one has passed its startup delay.
+
+/// M-cycles between writing 0xFF46 and the first byte being copied
+const STARTUP_DELAY: u8 = 1;
+/// Bytes copied by a transfer
+const TRANSFER_LENGTH: u16 = 0xA0;
+
+/// The bus an address is on
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Bus {
+    External,
+    Video,
+    Internal, // OAM, I/O registers & HRAM
+}
+
+impl Bus {
+    pub fn of(addr: u16) -> Bus {
+        match addr {
+            0x8000 ..= 0x9FFF => Bus::Video,
+            0xFE00 ..= 0xFFFF => Bus::Internal,
+            _ => Bus::External,
+        }
+    }
+}
+
+pub struct Dma {
+    reg: u8, // last value written to 0xFF46
+    source: u16,
+    index: u16, // next byte to copy
+    active: bool,
+    pending: Option<(u16, u8)>, // source & remaining delay of a new transfer
+    cycles: u32, // clock cycles into the current M-cycle
+    byte: u8, // last byte copied
+}
+
+impl Dma {
+    pub fn new() -> Dma {
+        Dma {
+            reg: 0xFF,
+            source: 0x0000,
+            index: 0,
+            active: false,
+            pending: None,
+            cycles: 0,
+            byte: 0xFF,
+        }
+    }
+
+    /// Reads the DMA register (0xFF46)
+    pub fn read(&self) -> u8 {
+        self.reg
+    }
+
+    /// Writes the DMA register (0xFF46), starting a transfer
+    pub fn write(&mut self, val: u8) {
+        self.reg = val;
+        self.pending = Some(((val as u16) << 8, STARTUP_DELAY));
+    }
+
+    /// Sets the DMA register (0xFF46) without starting a transfer
+    pub fn poke(&mut self, val: u8) {
+        self.reg = val;
+    }
+
+    /// Returns if a transfer is copying bytes
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Returns the last byte copied; what the CPU sees on the DMA's bus
+    pub fn byte(&self) -> u8 {
+        self.byte
+    }
+
+    /// Returns the bus the transfer reads from
+    pub fn bus(&self) -> Bus {
+        Bus::of(self.source)
+    }
+
+    /// Advances by `cycles` clock cycles and returns the M-cycles passed
+    pub fn clock(&mut self, cycles: u32) -> u32 {
+        if !self.active && self.pending.is_none() {
+            return 0;
+        }
+        self.cycles += cycles;
+        let m_cycles = self.cycles / 4;
+        self.cycles %= 4;
+        m_cycles
+    }
+
+    /// Does one M-cycle of the transfer; returns the source address & OAM
+    /// index of the byte to copy, if any.
+    pub fn next_transfer(&mut self) -> Option<(u16, usize)> {
+        if let Some((source, delay)) = self.pending {
+            if delay == 0 {
+                self.pending = None;
+                self.source = source;
+                self.index = 0;
+                self.active = true;
+            } else {
+                self.pending = Some((source, delay - 1));
+            }
+        }
+        if !self.active {
+            return None;
+        }
+        let transfer = (self.source + self.index, self.index as usize);
+        self.index += 1;
+        if self.index == TRANSFER_LENGTH {
+            self.active = false;
+        }
+        Some(transfer)
+    }
+
+    /// Stores the byte copied by the last transfer
+    pub fn set_byte(&mut self, byte: u8) {
+        self.byte = byte;
+    }
+}
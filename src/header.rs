@@ -0,0 +1,376 @@
+// The cartridge header lives at 0x0100 - 0x014F of the ROM; see the memory
+// map documentation of the MMU for the layout.
+//
+// The boot ROM refuses to start a cartridge whose header checksum (0x014D)
+// doesn't match the complement of bytes 0x0134 - 0x014C; the global checksum
+// (0x014E - 0x014F) is never checked by the hardware.
+
+use std::error::Error;
+use std::fmt;
+
+/// Length of the ROM up to and including the header
+const HEADER_END: usize = 0x0150;
+
+/// Errors found while parsing a cartridge header
+#[derive(Debug, PartialEq, Clone)]
+pub enum HeaderError {
+    TooShort(usize),
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    HeaderChecksum { expected: u8, computed: u8 },
+    GlobalChecksum { expected: u16, computed: u16 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::TooShort(len) =>
+                write!(f, "Rom of {} bytes is too short to contain a header", len),
+            HeaderError::UnknownCartridgeType(code) =>
+                write!(f, "Unknown cartridge type 0x{:02X}", code),
+            HeaderError::UnknownRomSize(code) =>
+                write!(f, "Unknown rom size 0x{:02X}", code),
+            HeaderError::UnknownRamSize(code) =>
+                write!(f, "Unknown ram size 0x{:02X}", code),
+            HeaderError::HeaderChecksum { expected, computed } =>
+                write!(f, "Header checksum is 0x{:02X} but should be 0x{:02X}", expected, computed),
+            HeaderError::GlobalChecksum { expected, computed } =>
+                write!(f, "Global checksum is 0x{:04X} but should be 0x{:04X}", expected, computed),
+        }
+    }
+}
+
+impl Error for HeaderError { }
+
+/// The memory bank controller and extra hardware of a cartridge (0x0147)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Result<CartridgeType, HeaderError> {
+        use self::CartridgeType::*;
+        Ok(match code {
+            0x00 => RomOnly,
+            0x01 => Mbc1,
+            0x02 => Mbc1Ram,
+            0x03 => Mbc1RamBattery,
+            0x05 => Mbc2,
+            0x06 => Mbc2Battery,
+            0x08 => RomRam,
+            0x09 => RomRamBattery,
+            0x0B => Mmm01,
+            0x0C => Mmm01Ram,
+            0x0D => Mmm01RamBattery,
+            0x0F => Mbc3TimerBattery,
+            0x10 => Mbc3TimerRamBattery,
+            0x11 => Mbc3,
+            0x12 => Mbc3Ram,
+            0x13 => Mbc3RamBattery,
+            0x19 => Mbc5,
+            0x1A => Mbc5Ram,
+            0x1B => Mbc5RamBattery,
+            0x1C => Mbc5Rumble,
+            0x1D => Mbc5RumbleRam,
+            0x1E => Mbc5RumbleRamBattery,
+            0xFC => PocketCamera,
+            0xFD => BandaiTama5,
+            0xFE => HuC3,
+            0xFF => HuC1RamBattery,
+            _ => return Err(HeaderError::UnknownCartridgeType(code)),
+        })
+    }
+
+    /// Returns if the cartridge has external RAM
+    /// MBC2 has 512 half-bytes of RAM built into the controller.
+    pub fn has_ram(self) -> bool {
+        use self::CartridgeType::*;
+        matches!(self,
+            Mbc1Ram | Mbc1RamBattery | Mbc2 | Mbc2Battery | RomRam | RomRamBattery |
+            Mmm01Ram | Mmm01RamBattery | Mbc3TimerRamBattery | Mbc3Ram | Mbc3RamBattery |
+            Mbc5Ram | Mbc5RamBattery | Mbc5RumbleRam | Mbc5RumbleRamBattery |
+            PocketCamera | HuC3 | HuC1RamBattery)
+    }
+
+    /// Returns if the RAM (or clock) is kept alive by a battery
+    pub fn has_battery(self) -> bool {
+        use self::CartridgeType::*;
+        matches!(self,
+            Mbc1RamBattery | Mbc2Battery | RomRamBattery | Mmm01RamBattery |
+            Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3RamBattery | Mbc5RamBattery |
+            Mbc5RumbleRamBattery | PocketCamera | HuC3 | HuC1RamBattery)
+    }
+
+    /// Returns if the cartridge has a real time clock
+    pub fn has_timer(self) -> bool {
+        self == CartridgeType::Mbc3TimerBattery || self == CartridgeType::Mbc3TimerRamBattery
+    }
+
+    /// Returns if the cartridge has a rumble motor
+    pub fn has_rumble(self) -> bool {
+        use self::CartridgeType::*;
+        matches!(self, Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery)
+    }
+}
+
+/// Color Game Boy support (0x0143)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CgbSupport {
+    None,
+    Compatible, // 0x80 - works on both the DMG & CGB
+    Only, // 0xC0 - only works on the CGB
+}
+
+/// Publisher of the cartridge
+#[derive(Debug, PartialEq, Clone)]
+pub enum Licensee {
+    Old(u8), // 0x014B
+    New(String), // 0x0144 - 0x0145, used when 0x014B is 0x33
+}
+
+/// Destination code (0x014A)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Destination {
+    Japanese,
+    NonJapanese,
+}
+
+/// The parsed contents of the cartridge header
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer: Option<String>, // 4 character code of newer CGB games
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub licensee: Licensee,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize, // in bytes
+    pub ram_size: usize, // in bytes
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub global_checksum_ok: bool, // the hardware ignores the global checksum
+}
+
+impl CartridgeHeader {
+    /// Parses the header of `rom`, checking the header checksum like the
+    /// boot ROM does.
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, HeaderError> {
+        if rom.len() < HEADER_END {
+            return Err(HeaderError::TooShort(rom.len()));
+        }
+
+        let computed = CartridgeHeader::compute_header_checksum(rom);
+        if computed != rom[0x014D] {
+            return Err(HeaderError::HeaderChecksum { expected: rom[0x014D], computed });
+        }
+
+        let cgb = match rom[0x0143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+        let licensee = if rom[0x014B] == 0x33 {
+            Licensee::New(ascii(&rom[0x0144..0x0146]))
+        } else {
+            Licensee::Old(rom[0x014B])
+        };
+
+        // CGB games shortened the title to make room for the CGB flag and
+        // later on for a manufacturer code.
+        let manufacturer_code = &rom[0x013F..0x0143];
+        let has_manufacturer = cgb != CgbSupport::None
+            && manufacturer_code.iter().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit());
+        let (title, manufacturer) = if has_manufacturer {
+            (ascii(&rom[0x0134..0x013F]), Some(ascii(manufacturer_code)))
+        } else if cgb != CgbSupport::None {
+            (ascii(&rom[0x0134..0x0143]), None)
+        } else {
+            (ascii(&rom[0x0134..0x0144]), None)
+        };
+
+        let rom_size = match rom[0x0148] {
+            code @ 0x00 ..= 0x08 => 0x8000 << code,
+            0x52 => 72 * 0x4000,
+            0x53 => 80 * 0x4000,
+            0x54 => 96 * 0x4000,
+            code => return Err(HeaderError::UnknownRomSize(code)),
+        };
+        let ram_size = match rom[0x0149] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(HeaderError::UnknownRamSize(code)),
+        };
+
+        let global_checksum = (rom[0x014E] as u16) << 8 | rom[0x014F] as u16;
+        Ok(CartridgeHeader {
+            title,
+            manufacturer,
+            cgb,
+            sgb: rom[0x0146] == 0x03,
+            licensee,
+            cartridge_type: CartridgeType::from_code(rom[0x0147])?,
+            rom_size,
+            ram_size,
+            destination: if rom[0x014A] == 0x00 { Destination::Japanese } else { Destination::NonJapanese },
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum,
+            global_checksum_ok: CartridgeHeader::compute_global_checksum(rom) == global_checksum,
+        })
+    }
+
+    /// Checks the global checksum against the contents of `rom`
+    pub fn verify_global_checksum(&self, rom: &[u8]) -> Result<(), HeaderError> {
+        let computed = CartridgeHeader::compute_global_checksum(rom);
+        if computed != self.global_checksum {
+            return Err(HeaderError::GlobalChecksum { expected: self.global_checksum, computed });
+        }
+        Ok(())
+    }
+
+    /// Returns the number of 16KB ROM banks
+    pub fn rom_banks(&self) -> usize {
+        self.rom_size / 0x4000
+    }
+
+    /// Sum of all bytes of the ROM except for the global checksum itself
+    fn compute_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter().enumerate()
+            .filter(|&(addr, _)| addr != 0x014E && addr != 0x014F)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+    }
+
+    /// Complement check of 0x0134 - 0x014C as done by the boot ROM
+    fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[0x0134..0x014D].iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1))
+    }
+}
+
+/// Converts a 0x00 padded ASCII field to a string
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&b| b != 0x00)
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a 64KB MBC1 ROM titled "TEST" with valid checksums
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x10000];
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom[0x0147] = 0x03;
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x02;
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[0x014D] = CartridgeHeader::compute_header_checksum(rom);
+        let global = CartridgeHeader::compute_global_checksum(rom);
+        rom[0x014E] = (global >> 8) as u8;
+        rom[0x014F] = global as u8;
+    }
+
+    #[test]
+    fn parses_header() {
+        let header = CartridgeHeader::parse(&rom()).unwrap();
+        assert_eq!(header.title, "TEST");
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert_eq!(header.cartridge_type, CartridgeType::Mbc1RamBattery);
+        assert_eq!(header.rom_size, 0x10000);
+        assert_eq!(header.rom_banks(), 4);
+        assert_eq!(header.ram_size, 0x2000);
+        assert!(header.global_checksum_ok);
+    }
+
+    #[test]
+    fn header_checksum() {
+        // 0x0134 - 0x014C all zero: 0 - 25 * 1
+        let mut rom = vec![0; HEADER_END];
+        assert_eq!(CartridgeHeader::compute_header_checksum(&rom), 0xE7);
+
+        rom[0x014D] = 0xE7;
+        assert!(CartridgeHeader::parse(&rom).is_ok());
+        rom[0x0134] = b'X';
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap_err(),
+                   HeaderError::HeaderChecksum { expected: 0xE7, computed: 0x8F });
+    }
+
+    #[test]
+    fn global_checksum() {
+        let mut rom = rom();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.verify_global_checksum(&rom), Ok(()));
+
+        // The header checksum still matches, so the boot ROM would start it
+        rom[0x4000] = 0x01;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.global_checksum_ok);
+        assert_eq!(header.verify_global_checksum(&rom), Err(HeaderError::GlobalChecksum {
+            expected: header.global_checksum,
+            computed: header.global_checksum.wrapping_add(1),
+        }));
+    }
+
+    #[test]
+    fn rejects_short_roms_and_unknown_sizes() {
+        assert_eq!(CartridgeHeader::parse(&[0; 0x100]).unwrap_err(), HeaderError::TooShort(0x100));
+
+        let mut rom = rom();
+        rom[0x0148] = 0x09;
+        fix_checksums(&mut rom);
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap_err(), HeaderError::UnknownRomSize(0x09));
+    }
+
+    #[test]
+    fn cgb_titles() {
+        let mut rom = rom();
+        rom[0x0134..0x0144].copy_from_slice(b"POKEMON_SLVAAXE\xC0");
+        fix_checksums(&mut rom);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.cgb, CgbSupport::Only);
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer, Some("AAXE".to_owned()));
+    }
+}
@@ -0,0 +1,142 @@
+// The CPU has five interrupt sources. Each one has a bit in the interrupt
+// enable (IE, 0xFFFF) and interrupt flag (IF, 0xFF0F) registers:
+//
+// Bit | Interrupt | Vector
+// ----------------------------
+//  0  | V-Blank   | 0x0040
+//  1  | LCD STAT  | 0x0048
+//  2  | Timer     | 0x0050
+//  3  | Serial    | 0x0058
+//  4  | Joypad    | 0x0060
+//
+// A component raises an interrupt by setting its bit in IF. When the CPU's
+// interrupt master enable (IME) is set and a bit is set in both IE and IF the
+// CPU jumps to the vector of the lowest set bit, which has the highest
+// priority. Only the lower five bits of both registers are connected; the
+// upper three bits of IF always read back as 1 while those of IE are plain
+// read/write storage.
+
+/// The interrupt sources in order of priority
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// Returns the bit mask of the interrupt in the IE & IF registers
+    pub fn mask(self) -> u8 {
+        match self {
+            Interrupt::VBlank  => 0x01,
+            Interrupt::LcdStat => 0x02,
+            Interrupt::Timer   => 0x04,
+            Interrupt::Serial  => 0x08,
+            Interrupt::Joypad  => 0x10,
+        }
+    }
+
+    /// Returns the address the CPU jumps to when servicing the interrupt
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank  => 0x0040,
+            Interrupt::LcdStat => 0x0048,
+            Interrupt::Timer   => 0x0050,
+            Interrupt::Serial  => 0x0058,
+            Interrupt::Joypad  => 0x0060,
+        }
+    }
+}
+
+/// Holds the interrupt enable (IE) and interrupt flag (IF) registers
+#[derive(Debug)]
+pub struct InterruptController {
+    enable: u8,
+    flag: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController {
+            enable: 0x00,
+            flag: 0x00,
+        }
+    }
+
+    /// Raises the interrupt line of `int` by setting its bit in IF
+    pub fn request(&mut self, int: Interrupt) {
+        self.flag |= int.mask();
+    }
+
+    /// Clears the bit of `int` in IF; done by the CPU when it is serviced
+    pub fn acknowledge(&mut self, int: Interrupt) {
+        self.flag &= !int.mask();
+    }
+
+    /// Returns the interrupts which are both enabled and requested
+    pub fn pending(&self) -> u8 {
+        self.enable & self.flag & 0x1F
+    }
+
+    /// Returns the highest priority pending interrupt, if any
+    pub fn next(&self) -> Option<Interrupt> {
+        let pending = self.pending();
+        [
+            Interrupt::VBlank,
+            Interrupt::LcdStat,
+            Interrupt::Timer,
+            Interrupt::Serial,
+            Interrupt::Joypad,
+        ].iter().cloned().find(|int| pending & int.mask() != 0)
+    }
+
+    /// Reads the IE register (0xFFFF)
+    pub fn read_ie(&self) -> u8 { self.enable }
+
+    /// Writes the IE register (0xFFFF)
+    pub fn write_ie(&mut self, val: u8) { self.enable = val; }
+
+    /// Reads the IF register (0xFF0F)
+    pub fn read_if(&self) -> u8 { self.flag | 0xE0 }
+
+    /// Writes the IF register (0xFF0F)
+    pub fn write_if(&mut self, val: u8) { self.flag = val & 0x1F; }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowest_bit_first() {
+        let mut int = InterruptController::new();
+        int.request(Interrupt::Joypad);
+        int.request(Interrupt::Timer);
+        int.request(Interrupt::LcdStat);
+        assert_eq!(int.next(), None);
+
+        int.write_ie(0x1C);
+        assert_eq!(int.pending(), 0x14);
+        assert_eq!(int.next(), Some(Interrupt::Timer));
+        int.acknowledge(Interrupt::Timer);
+        assert_eq!(int.next(), Some(Interrupt::Joypad));
+        int.write_ie(0xFF);
+        assert_eq!(int.next(), Some(Interrupt::LcdStat));
+    }
+
+    #[test]
+    fn unused_bits() {
+        let mut int = InterruptController::new();
+        int.write_if(0xFF);
+        assert_eq!(int.read_if(), 0xFF);
+        int.write_if(0x00);
+        assert_eq!(int.read_if(), 0xE0);
+        // IE keeps all 8 bits but the upper ones never make an interrupt
+        int.write_ie(0xE0);
+        assert_eq!(int.read_ie(), 0xE0);
+        int.write_if(0xFF);
+        assert_eq!(int.pending(), 0x00);
+    }
+}
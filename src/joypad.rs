@@ -0,0 +1,159 @@
+// The eight buttons are wired as a 2x4 matrix which is read through the P1
+// register:
+//
+// 0xFF00 : P1 - Joypad
+//          5 - P15 Select Action Buttons    (0=Select)
+//          4 - P14 Select Direction Buttons (0=Select)
+//          3 - P13 Input Down  or Start     (0=Pressed) (Read Only)
+//          2 - P12 Input Up    or Select    (0=Pressed) (Read Only)
+//          1 - P11 Input Left  or Button B  (0=Pressed) (Read Only)
+//          0 - P10 Input Right or Button A  (0=Pressed) (Read Only)
+//
+// All lines are active low. When both groups are selected the input lines
+// read as the AND of both groups, and when neither is selected they read 1.
+// The joypad interrupt is requested when one of the input lines P10-P13 goes
+// from high to low, either by pressing a button or by selecting a group in
+// which a button is held down.
+
+use interrupt::{Interrupt, InterruptController};
+
+/// The buttons of the Game Boy
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Returns the bit of the button; the directions are in the lower nibble
+    /// and the action buttons in the upper nibble.
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right  => 0x01,
+            Button::Left   => 0x02,
+            Button::Up     => 0x04,
+            Button::Down   => 0x08,
+            Button::A      => 0x10,
+            Button::B      => 0x20,
+            Button::Select => 0x40,
+            Button::Start  => 0x80,
+        }
+    }
+}
+
+pub struct Joypad {
+    pressed: u8, // held buttons, see `Button::mask`
+    select: u8, // P14 & P15
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            pressed: 0x00,
+            select: 0x30,
+        }
+    }
+
+    /// Holds down `button`
+    pub fn press(&mut self, button: Button, int: &mut InterruptController) {
+        let lines = self.lines();
+        self.pressed |= button.mask();
+        self.check_interrupt(lines, int);
+    }
+
+    /// Lets go of `button`
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    /// Reads the P1 register (0xFF00)
+    pub fn rb(&self) -> u8 {
+        0xC0 | self.select | !self.lines() & 0x0F
+    }
+
+    /// Sets the select lines of P1 (0xFF00) without requesting an interrupt
+    pub fn poke(&mut self, val: u8) {
+        self.select = val & 0x30;
+    }
+
+    /// Writes the P1 register (0xFF00); only the select lines are writable
+    pub fn wb(&mut self, val: u8, int: &mut InterruptController) {
+        let lines = self.lines();
+        self.select = val & 0x30;
+        self.check_interrupt(lines, int);
+    }
+
+    /// Returns the input lines P10-P13 which are pulled low, as set bits
+    pub fn lines(&self) -> u8 {
+        let mut lines = 0x00;
+        if self.select & 0x10 == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            lines |= self.pressed >> 4;
+        }
+        lines
+    }
+
+    /// Requests the joypad interrupt if an input line went from high to low
+    fn check_interrupt(&self, old_lines: u8, int: &mut InterruptController) {
+        if self.lines() & !old_lines != 0 {
+            int.request(Interrupt::Joypad);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matrix() {
+        let mut int = InterruptController::new();
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Left, &mut int);
+        joypad.press(Button::Start, &mut int);
+        joypad.press(Button::A, &mut int);
+        assert_eq!(joypad.rb(), 0xFF);
+
+        joypad.wb(0x20, &mut int);
+        assert_eq!(joypad.rb(), 0xED);
+        joypad.wb(0x10, &mut int);
+        assert_eq!(joypad.rb(), 0xD6);
+        joypad.wb(0x00, &mut int);
+        assert_eq!(joypad.rb(), 0xC4);
+
+        joypad.release(Button::Start);
+        joypad.release(Button::Left);
+        assert_eq!(joypad.rb(), 0xCE);
+        // Only the select lines are writable
+        joypad.wb(0xDF, &mut int);
+        assert_eq!(joypad.rb(), 0xDE);
+    }
+
+    #[test]
+    fn interrupt_on_falling_lines() {
+        let mut int = InterruptController::new();
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Down, &mut int);
+        assert_eq!(int.read_if() & 0x10, 0x00);
+
+        // Selecting a group with a held button pulls its line low
+        joypad.wb(0x20, &mut int);
+        assert_eq!(int.read_if() & 0x10, 0x10);
+
+        // Start shares the already low line of Down
+        int.write_if(0x00);
+        joypad.wb(0x00, &mut int);
+        joypad.press(Button::Start, &mut int);
+        assert_eq!(int.read_if() & 0x10, 0x00);
+        joypad.press(Button::B, &mut int);
+        assert_eq!(int.read_if() & 0x10, 0x10);
+    }
+}
@@ -1,25 +1,86 @@
 #![allow(dead_code)]
+#![allow(clippy::upper_case_acronyms)]
 
 // Module defines
+mod apu;
+mod boot;
+mod cartridge;
 mod cpu;
+mod dma;
+mod header;
+mod interrupt;
+mod joypad;
+mod md5;
 mod mmu;
+mod ppu;
+mod serial;
+mod timer;
+pub mod screen;
+
+pub use header::{CartridgeHeader, CartridgeType, CgbSupport, Destination, HeaderError, Licensee};
+pub use boot::Model;
+pub use cartridge::RtcClock;
+pub use joypad::Button;
+pub use mmu::MemoryBus;
+pub use rgb_error::RgbError;
+pub use serial::Link;
 
 mod rgb_error {
+    use std::error::Error;
     use std::fmt;
+    use std::io;
+
+    use ::header::{CartridgeType, HeaderError};
 
+    /// Errors reported by the emulator instead of aborting
+    #[derive(Debug)]
     pub enum RgbError {
-        BootRomLength,
+        Io(io::Error), // reading a rom or boot rom failed
+        Header(HeaderError),
+        UnsupportedCartridge(CartridgeType),
+        BootRomLength(usize),
         BootRomBad,
+        IllegalOpcode { opcode: u8, addr: u16 },
+        Save(io::Error), // reading or writing the save file failed
     }
 
     impl fmt::Display for RgbError {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             match *self {
-                RgbError::BootRomBad => write!(f, "Bad byte in boot rom at..."),
-                RgbError::BootRomLength => write!(f, "Boot rom length is wrong"),
+                RgbError::Io(ref err) => write!(f, "{}", err),
+                RgbError::Header(ref err) => write!(f, "Invalid cartridge header: {}", err),
+                RgbError::UnsupportedCartridge(cartridge_type) =>
+                    write!(f, "Unsupported cartridge type {:?}", cartridge_type),
+                RgbError::BootRomLength(len) => write!(f, "Boot rom length of {} bytes is wrong", len),
+                RgbError::BootRomBad => write!(f, "Boot rom doesn't match any known boot rom"),
+                RgbError::IllegalOpcode { opcode, addr } =>
+                    write!(f, "Illegal opcode 0x{:02X} at 0x{:04X}", opcode, addr),
+                RgbError::Save(ref err) => write!(f, "Save file error: {}", err),
+            }
+        }
+    }
+
+    impl Error for RgbError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            match *self {
+                RgbError::Io(ref err) | RgbError::Save(ref err) => Some(err),
+                RgbError::Header(ref err) => Some(err),
+                _ => None,
             }
         }
     }
+
+    impl From<io::Error> for RgbError {
+        fn from(err: io::Error) -> RgbError {
+            RgbError::Io(err)
+        }
+    }
+
+    impl From<HeaderError> for RgbError {
+        fn from(err: HeaderError) -> RgbError {
+            RgbError::Header(err)
+        }
+    }
 }
 
 
@@ -29,8 +90,28 @@ pub fn version() -> u32 {
 }
 
 pub mod emulator_context {
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use ::boot::Model;
     use ::cpu;
-    use ::mmu;
+    use ::cpu::Register;
+    use ::mmu::{self, MemoryBus};
+    use ::rgb_error::RgbError;
+    use ::cartridge::RtcClock;
+    use ::header::CartridgeHeader;
+    use ::joypad::Button;
+    use ::serial::Link;
+
+    /// Clock cycles between writes of the save file (about a second)
+    const SAVE_INTERVAL: u64 = 4_194_304;
+
+    /// Receives the address & disassembly of executed instructions
+    type TraceCallback = dyn FnMut(u16, &str);
+
+    /// Receives the error when the save file can't be written on drop
+    type SaveErrorCallback = dyn FnMut(&RgbError);
 
     /// The Emulator context holds all of pieces to the running state of an
     // emulator.
@@ -38,6 +119,14 @@ pub mod emulator_context {
         cycles: u64,
         cpu: cpu::CPU,
         mmu: mmu::MMU,
+        rumble: bool, // last reported state of the rumble motor
+        rumble_callback: Option<Box<dyn FnMut(bool)>>,
+        lockup_callback: Option<Box<dyn FnMut(u8, u16)>>,
+        trace_callback: Option<Box<TraceCallback>>,
+        save_error_callback: Option<Box<SaveErrorCallback>>,
+        save_path: Option<PathBuf>, // `.sav` file of a battery backed cartridge
+        saved: Vec<u8>, // contents of the save file
+        save_cycles: u64, // cycles at the last check of the save file
     }
 
     pub fn new() -> EmulatorContext {
@@ -45,39 +134,314 @@ pub mod emulator_context {
             cycles: 0,
             cpu: cpu::CPU::new(),
             mmu: mmu::MMU::new(),
+            rumble: false,
+            rumble_callback: None,
+            lockup_callback: None,
+            trace_callback: None,
+            save_error_callback: None,
+            save_path: None,
+            saved: Vec::new(),
+            save_cycles: 0,
         }
     }
 
     impl EmulatorContext {
 
-        pub fn load_bytes(&mut self, bytes: &[u8]) {
+        /// Loads raw bytes as a cartridge without a header or MBC. The save
+        /// file of the previous cartridge is written first.
+        pub fn load_bytes(&mut self, bytes: &[u8]) -> Result<(), RgbError> {
+            self.flush_save()?;
+            self.save_path = None;
+            self.saved.clear();
             self.mmu.load_bytes(bytes);
+            Ok(())
+        }
+
+        /// Selects the emulated Game Boy model
+        /// Without a boot ROM of the model loaded, the boot ROM has to be
+        /// skipped with `skip_boot_rom`.
+        pub fn set_model(&mut self, model: Model) {
+            self.mmu.set_model(model);
+        }
+
+        /// Loads the boot ROM at `path`; the model is selected by the dump.
+        pub fn load_boot_rom<P: AsRef<Path>>(&mut self, path: P) -> Result<Model, RgbError> {
+            let rom = fs::read(path)?;
+            self.mmu.load_boot_rom(rom)
+        }
+
+        /// Skips the boot ROM by setting up the registers it leaves behind and
+        /// starting the cartridge at 0x0100.
+        pub fn skip_boot_rom(&mut self) {
+            self.mmu.skip_boot_rom();
+            let model = self.mmu.model();
+            let regs = model.post_boot_registers(self.mmu.cgb_mode());
+            self.cpu.set_r16(Register::AF, regs[0]);
+            self.cpu.set_r16(Register::BC, regs[1]);
+            self.cpu.set_r16(Register::DE, regs[2]);
+            self.cpu.set_r16(Register::HL, regs[3]);
+            self.cpu.set_r16(Register::SP, 0xFFFE);
+            self.cpu.set_pc(0x0100);
+        }
+
+        /// Resets the Game Boy like turning it off and on again, which also
+        /// ends a lockup. The cartridge stays inserted and the boot ROM runs
+        /// again, unless it's skipped with `skip_boot_rom`.
+        pub fn reset(&mut self) {
+            self.cpu.reset();
+            self.mmu.reset();
         }
 
-        pub fn step(&mut self) {
+        /// Loads the cartridge at `path`. For cartridges with a battery the
+        /// save file next to it (with a `.sav` extension) is loaded as well
+        /// and kept up to date while running.
+        pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), RgbError> {
+            let path = path.as_ref();
+            self.flush_save()?;
+            self.save_path = None;
+            self.saved.clear();
+            let rom = fs::read(path)?;
+            self.mmu.load_rom(rom)?;
+            if self.mmu.has_battery() {
+                let save_path = path.with_extension("sav");
+                match fs::read(&save_path) {
+                    Ok(data) => self.mmu.cartridge_mut().load(&data),
+                    Err(ref err) if err.kind() == io::ErrorKind::NotFound => { },
+                    Err(err) => return Err(RgbError::Save(err)),
+                }
+                self.saved = self.mmu.cartridge().save();
+                self.mmu.cartridge_mut().mark_saved();
+                self.save_path = Some(save_path);
+            }
+            Ok(())
+        }
+
+        /// Writes the battery backed RAM to the save file if it changed.
+        /// Dropping the context also writes it, but can only report a failure
+        /// through `set_save_error_callback`; call this first to handle it.
+        pub fn flush_save(&mut self) -> Result<(), RgbError> {
+            if let Some(ref path) = self.save_path {
+                if self.mmu.cartridge().save_changed(&self.saved) {
+                    let data = self.mmu.cartridge().save();
+                    fs::write(path, &data).map_err(RgbError::Save)?;
+                    self.saved = data;
+                    self.mmu.cartridge_mut().mark_saved();
+                }
+            }
+            Ok(())
+        }
+
+        /// Returns the battery backed RAM in the format of a save file
+        pub fn export_save(&self) -> Vec<u8> {
+            self.mmu.cartridge().save()
+        }
+
+        /// Replaces the battery backed RAM with the contents of a save file
+        pub fn import_save(&mut self, data: &[u8]) {
+            self.mmu.cartridge_mut().load(data);
+        }
+
+        /// Returns the header of the loaded cartridge
+        pub fn cartridge_header(&self) -> Option<&CartridgeHeader> {
+            self.mmu.header()
+        }
+
+        /// Reads `addr` for debugging without side effects or timing
+        pub fn peek(&self, addr: u16) -> u8 {
+            self.mmu.peek(addr)
+        }
+
+        /// Writes `addr` for debugging without side effects or timing
+        pub fn poke(&mut self, addr: u16, val: u8) {
+            self.mmu.poke(addr, val);
+        }
+
+        /// Reads `addr` of cartridge ROM bank `bank` for debugging
+        pub fn peek_rom(&self, bank: usize, addr: u16) -> u8 {
+            self.mmu.peek_rom(bank, addr)
+        }
+
+        /// Patches `addr` of cartridge ROM bank `bank` for debugging
+        pub fn poke_rom(&mut self, bank: usize, addr: u16, val: u8) {
+            self.mmu.poke_rom(bank, addr, val);
+        }
+
+        /// Reads `addr` of cartridge RAM bank `bank` for debugging
+        pub fn peek_ram(&self, bank: usize, addr: u16) -> u8 {
+            self.mmu.peek_ram(bank, addr)
+        }
+
+        /// Writes `addr` of cartridge RAM bank `bank` for debugging
+        pub fn poke_ram(&mut self, bank: usize, addr: u16, val: u8) {
+            self.mmu.poke_ram(bank, addr, val);
+        }
+
+        /// Selects the time source of the cartridge's real time clock
+        pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+            self.mmu.cartridge_mut().set_rtc_clock(clock);
+        }
+
+        /// Sets the function called with the new state of the cartridge's
+        /// rumble motor whenever it's turned on or off.
+        pub fn set_rumble_callback<F: FnMut(bool) + 'static>(&mut self, callback: F) {
+            self.rumble_callback = Some(Box::new(callback));
+        }
+
+        /// Calls `callback` with the opcode & its address when an illegal
+        /// opcode locks up the CPU.
+        pub fn set_lockup_callback<F: FnMut(u8, u16) + 'static>(&mut self, callback: F) {
+            self.lockup_callback = Some(Box::new(callback));
+        }
+
+        /// Calls `callback` with the address & disassembly of every executed
+        /// instruction; slows down emulation a lot.
+        pub fn set_trace_callback<F: FnMut(u16, &str) + 'static>(&mut self, callback: F) {
+            self.trace_callback = Some(Box::new(callback));
+        }
+
+        /// Calls `callback` when the save file can't be written while the
+        /// context is dropped.
+        pub fn set_save_error_callback<F: FnMut(&RgbError) + 'static>(&mut self, callback: F) {
+            self.save_error_callback = Some(Box::new(callback));
+        }
+
+        /// Returns if an illegal opcode locked up the CPU
+        pub fn is_locked(&self) -> bool {
+            self.cpu.is_locked()
+        }
+
+        /// Returns the last drawn frame as 160x144 shades from 0 (white) to
+        /// 3 (black), row by row.
+        pub fn framebuffer(&self) -> &[u8] {
+            self.mmu.ppu().frame()
+        }
+
+        /// Returns if a new frame has been completed since the last call
+        pub fn frame_ready(&mut self) -> bool {
+            self.mmu.ppu_mut().frame_ready()
+        }
+
+        /// Returns if the game has ever turned the LCD off outside of V-Blank
+        pub fn unsafe_lcd_off(&self) -> bool {
+            self.mmu.ppu().unsafe_lcd_off()
+        }
+
+        /// Holds down `button` until it's released
+        pub fn press(&mut self, button: Button) {
+            self.mmu.press_button(button);
+        }
+
+        /// Lets go of `button`
+        pub fn release(&mut self, button: Button) {
+            self.mmu.release_button(button);
+        }
+
+        /// Plugs `link` into the serial port
+        pub fn connect_link(&mut self, link: Box<dyn Link>) {
+            self.mmu.serial_mut().connect(link);
+        }
+
+        /// Unplugs the link cable, returning the link that was plugged in
+        pub fn disconnect_link(&mut self) -> Option<Box<dyn Link>> {
+            self.mmu.serial_mut().disconnect()
+        }
+
+        /// Sets the rate in Hz at which audio samples are produced
+        pub fn set_sample_rate(&mut self, sample_rate: u32) {
+            self.mmu.apu_mut().set_sample_rate(sample_rate);
+        }
+
+        /// Returns the audio samples produced since the last call,
+        /// interleaved as left, right in the range -1 to 1. Only the last
+        /// second of samples is kept between calls.
+        pub fn drain_samples(&mut self) -> Vec<f32> {
+            self.mmu.apu_mut().drain_samples()
+        }
+
+        /// Executes a single instruction (or services an interrupt) and
+        /// advances the rest of the machine by the cycles it took. Illegal
+        /// opcodes lock up the CPU (see `set_lockup_callback`) while the rest
+        /// of the machine keeps running. Fails on instructions that can't be
+        /// executed and when the save file can't be written.
+        pub fn step(&mut self) -> Result<(), RgbError> {
+            let start = self.cycles;
+            let result = self.execute();
+            // The memory accesses of the instruction already advanced the
+            // rest of the machine; catch up with its internal cycles.
+            let cycles = (self.cycles - start) as u32;
+            let bus_cycles = self.mmu.take_bus_cycles();
+            debug_assert!(bus_cycles <= cycles, "{} bus cycles in {} cycles", bus_cycles, cycles);
+            self.mmu.tick(cycles - bus_cycles.min(cycles));
+
+            let rumble = self.mmu.cartridge().rumble();
+            if rumble != self.rumble {
+                self.rumble = rumble;
+                if let Some(ref mut callback) = self.rumble_callback {
+                    callback(rumble);
+                }
+            }
+
+            if self.save_path.is_some() && self.cycles - self.save_cycles >= SAVE_INTERVAL {
+                self.save_cycles = self.cycles;
+                self.flush_save()?;
+            }
+            result
+        }
+
+        fn execute(&mut self) -> Result<(), RgbError> {
             use cpu::Instruction as I;
             use cpu::Register as R;
             use cpu::Register::BYTE as BYTE;
             use cpu::Register::WORD as WORD;
 
+            // A locked up CPU never executes again
+            if self.cpu.is_locked() {
+                self.cycles += 4;
+                return Ok(());
+            }
+            // A halted CPU idles until an enabled interrupt is requested
+            if self.cpu.is_halted() {
+                if self.mmu.interrupts().pending() == 0 {
+                    self.cycles += 4;
+                    return Ok(());
+                }
+                self.cpu.resume();
+            }
+            // A stopped CPU idles until a joypad line goes low
+            if self.cpu.is_stopped() {
+                if !self.mmu.joypad_line_low() {
+                    self.cycles += 4;
+                    return Ok(());
+                }
+                self.cpu.resume();
+            }
+
+            // Service the highest priority pending interrupt
+            if self.cpu.ime() {
+                if let Some(int) = self.mmu.interrupts().next() {
+                    self.mmu.interrupts().acknowledge(int);
+                    self.cpu.interrupt(&mut self.mmu, int);
+                    self.cycles += 20;
+                    return Ok(());
+                }
+            }
+            self.cpu.update_ime();
+
             // Start instruction fetch
             let inst_off = self.cpu.get_pc();
             let inst = {
                 // get the next instruction byte
-                let byte = self.cpu.p_fetch(&self.mmu);
+                let byte = self.cpu.p_fetch(&mut self.mmu);
                 // Decode the instruction
-                let inst = match cpu::CPU::decode(byte) {
+                match cpu::CPU::decode(byte) {
                     I::ExtInstr => {
                         // load another byte
-                        let byte = self.cpu.p_fetch(&self.mmu);
+                        let byte = self.cpu.p_fetch(&mut self.mmu);
                         // and decode the extended instr
-                        let _inst = cpu::CPU::decode_extended(byte);
-                        _inst
-
+                        cpu::CPU::decode_extended(byte)
                     },
                     inst => { inst }
-                };
-                inst
+                }
             };// end instruction fetch
 
             // Start Decode: find out if we need to load a byte/word for the instr.
@@ -88,86 +452,470 @@ pub mod emulator_context {
                 Word(u16),
             }
             let data = match inst {
+                I::ADD(_, BYTE) |
                 I::ADC(_, BYTE) |
+                I::SUB(_, BYTE) |
+                I::SBC(_, BYTE) |
                 I::AND(BYTE)    |
+                I::OR(BYTE)     |
+                I::XOR(BYTE)    |
+                I::CP(BYTE)     |
                 I::LD(_, BYTE)  |
-                I::LD(BYTE, _)  |
                 I::LDH(BYTE, _) |
                 I::LDH(_, BYTE) |
+                I::LDHL(_, BYTE) |
+                I::STOP         |
                 I::JR(BYTE)     |
                 I::JRNZ(BYTE)   |
                 I::JRZ(BYTE)    |
                 I::JRNC(BYTE)   |
-                I::JRC(BYTE)    |
-                I::CP(BYTE)
+                I::JRC(BYTE)
                     => {
-                        let byte = self.cpu.p_fetch(&self.mmu);
+                        let byte = self.cpu.p_fetch(&mut self.mmu);
                         RegData::Byte(byte)
                     }
-                I::LD(_, WORD) |
-                I::LD(WORD, _) |
-                I::CP(WORD)    |
-                I::CALL(WORD)  |
-                I::CALLZ(WORD) |
-                I::CALLC(WORD) |
-                I::RETC(WORD)
+                I::LD(_, WORD)  |
+                I::LD(WORD, _)  |
+                I::JP(WORD)     |
+                I::JPNZ(WORD)   |
+                I::JPZ(WORD)    |
+                I::JPNC(WORD)   |
+                I::JPC(WORD)    |
+                I::CALL(WORD)   |
+                I::CALLNZ(WORD) |
+                I::CALLZ(WORD)  |
+                I::CALLNC(WORD) |
+                I::CALLC(WORD)
                     => {
-                        let l_byte = self.cpu.p_fetch(&self.mmu) as u16;
-                        let h_byte = self.cpu.p_fetch(&self.mmu) as u16;
+                        let l_byte = self.cpu.p_fetch(&mut self.mmu) as u16;
+                        let h_byte = self.cpu.p_fetch(&mut self.mmu) as u16;
                         RegData::Word((h_byte << 8) + l_byte)
                 }
                 _ => { RegData::None },
             }; // End Decode
 
-            // Debug: print instruction
+            // Debug: report the instruction to the trace callback
+            if let Some(ref mut callback) = self.trace_callback {
+                let text = match data {
+                    RegData::Byte(data) => format!("{:?}, 0x{:02x}", inst, data),
+                    RegData::Word(data) => format!("{:?}, 0x{:04x}", inst, data),
+                    RegData::None => format!("{:?}", inst),
+                };
+                callback(inst_off, &text);
+            }
             let data: u16 = match data {
-                RegData::Byte(data) => {
-                    println!("0x{:04x}: {:?}, 0x{:02x}", inst_off, inst, data);
-                    data as u16
-                },
-                RegData::Word(data) => {
-                    println!("0x{:04x}: {:?}, 0x{:04x}", inst_off, inst, data);
-                    data
-                },
-                RegData::None => {
-                    println!("0x{:04x}: {:?}", inst_off, inst);
-                    0
-                }
+                RegData::Byte(data) => data as u16,
+                RegData::Word(data) => data,
+                RegData::None => 0,
             };
 
             // Start Execute
             match inst {
+                I::NOP => {
+                    self.cycles += 4;
+                },
+                // 8 & 16-bit loads
+                I::LD(WORD, R::SP) => {
+                    self.cpu.ld(&mut self.mmu, WORD, R::SP, data);
+                    self.cycles += 20;
+                },
+                I::LD(R::A, WORD) => {
+                    self.cpu.ld(&mut self.mmu, R::A, WORD, data);
+                    self.cycles += 16;
+                },
+                I::LD(WORD, R::A) => {
+                    self.cpu.ld(&mut self.mmu, WORD, R::A, data);
+                    self.cycles += 16;
+                },
                 I::LD(reg, WORD) => {
                     self.cpu.ld_r_w(reg, data);
                     self.cycles += 12;
                 },
-                I::XOR(reg) => {
-                    self.cpu.xor_r(&mut self.mmu, reg);
-                    self.cycles += 8;
+                I::LD(R::HL, BYTE) => {
+                    self.cpu.ld(&mut self.mmu, R::HL, BYTE, data);
+                    self.cycles += 12;
+                },
+                I::LD(dst, src) => {
+                    self.cpu.ld(&mut self.mmu, dst, src, data);
+                    self.cycles += match (dst, src) {
+                        (R::SP, R::HL) => 8,
+                        (_, R::HL) | (_, R::BC) | (_, R::DE) | (_, R::C0) |
+                        (R::HL, _) | (R::BC, _) | (R::DE, _) | (R::C0, _) |
+                        (_, BYTE) => 8,
+                        _ => 4,
+                    };
                 },
                 I::LDD(r_a, r_b) => {
                     self.cpu.ldd_r_r(&mut self.mmu, r_a, r_b);
                     self.cycles += 8;
                 },
+                I::LDI(r_a, r_b) => {
+                    self.cpu.ldi_r_r(&mut self.mmu, r_a, r_b);
+                    self.cycles += 8;
+                },
+                I::LDH(dst, _) => {
+                    self.cpu.ldh(&mut self.mmu, dst, data as u8);
+                    self.cycles += 12;
+                },
+                I::LDHL(_, _) => {
+                    self.cpu.ldhl(data as u8);
+                    self.cycles += 12;
+                },
+                I::PUSH(reg) => {
+                    self.cpu.push(&mut self.mmu, reg);
+                    self.cycles += 16;
+                },
+                I::POP(reg) => {
+                    self.cpu.pop(&mut self.mmu, reg);
+                    self.cycles += 12;
+                },
+                // 8-bit ALU
+                I::ADD(R::A, reg) | I::ADC(R::A, reg) |
+                I::SUB(R::A, reg) | I::SBC(R::A, reg) |
+                I::AND(reg) | I::OR(reg) | I::XOR(reg) | I::CP(reg) => {
+                    let val = self.cpu.read_r8(&mut self.mmu, reg, data as u8);
+                    match inst {
+                        I::ADD(_, _) => self.cpu.add_a(val),
+                        I::ADC(_, _) => self.cpu.adc_a(val),
+                        I::SUB(_, _) => self.cpu.sub_a(val),
+                        I::SBC(_, _) => self.cpu.sbc_a(val),
+                        I::AND(_) => self.cpu.and_a(val),
+                        I::OR(_) => self.cpu.or_a(val),
+                        I::XOR(_) => self.cpu.xor_a(val),
+                        _ => self.cpu.cp_a(val),
+                    }
+                    if reg == R::HL || reg == BYTE {
+                        self.cycles += 8;
+                    } else {
+                        self.cycles += 4;
+                    }
+                },
+                I::INC(reg) => {
+                    self.cpu.inc(&mut self.mmu, reg);
+                    self.cycles += if reg == R::HL { 12 } else { 4 };
+                },
+                I::DEC(reg) => {
+                    self.cpu.dec(&mut self.mmu, reg);
+                    self.cycles += if reg == R::HL { 12 } else { 4 };
+                },
+                // 16-bit ALU
+                I::ADD(R::HL, reg) => {
+                    self.cpu.add_hl(reg);
+                    self.cycles += 8;
+                },
+                I::ADD(R::SP, _) => {
+                    self.cpu.add_sp(data as u8);
+                    self.cycles += 16;
+                },
+                I::INC16(reg) => {
+                    self.cpu.inc16(reg);
+                    self.cycles += 8;
+                },
+                I::DEC16(reg) => {
+                    self.cpu.dec16(reg);
+                    self.cycles += 8;
+                },
+                // Misc.
+                I::DAA => { self.cpu.daa(); self.cycles += 4; },
+                I::CPL => { self.cpu.cpl(); self.cycles += 4; },
+                I::CCF => { self.cpu.ccf(); self.cycles += 4; },
+                I::SCF => { self.cpu.scf(); self.cycles += 4; },
+                I::RLCA => { self.cpu.rlca(); self.cycles += 4; },
+                I::RLA => { self.cpu.rla(); self.cycles += 4; },
+                I::RRCA => { self.cpu.rrca(); self.cycles += 4; },
+                I::RRA => { self.cpu.rra(); self.cycles += 4; },
+                I::DI => { self.cpu.di(); self.cycles += 4; },
+                I::EI => { self.cpu.ei(); self.cycles += 4; },
+                I::HALT => {
+                    let pending = self.mmu.interrupts().pending() != 0;
+                    self.cpu.halt(pending);
+                    self.cycles += 4;
+                },
+                I::STOP => {
+                    self.mmu.reset_div();
+                    if self.mmu.speed_switch_armed() {
+                        // The speed switch pauses the CPU for 2050 M-cycles
+                        self.mmu.switch_speed();
+                        self.cycles += 8200;
+                    } else {
+                        self.cpu.stop();
+                        self.cycles += 4;
+                    }
+                },
+                // Jumps
+                I::JP(_) | I::JPNZ(_) | I::JPZ(_) | I::JPNC(_) | I::JPC(_) => {
+                    if self.cpu.condition(inst) {
+                        self.cpu.jp(data);
+                        self.cycles += 16;
+                    } else {
+                        self.cycles += 12;
+                    }
+                },
+                I::JPHL => {
+                    self.cpu.jp_hl();
+                    self.cycles += 4;
+                },
+                I::JR(_) | I::JRNZ(_) | I::JRZ(_) | I::JRNC(_) | I::JRC(_) => {
+                    if self.cpu.condition(inst) {
+                        self.cpu.jr(data as u8);
+                        self.cycles += 12;
+                    } else {
+                        self.cycles += 8;
+                    }
+                },
+                // Calls, Resets & Returns
+                I::CALL(_) | I::CALLNZ(_) | I::CALLZ(_) | I::CALLNC(_) | I::CALLC(_) => {
+                    if self.cpu.condition(inst) {
+                        self.cpu.call(&mut self.mmu, data);
+                        self.cycles += 24;
+                    } else {
+                        self.cycles += 12;
+                    }
+                },
+                I::RST(vector) => {
+                    self.cpu.rst(&mut self.mmu, vector);
+                    self.cycles += 16;
+                },
+                I::RET => {
+                    self.cpu.ret(&mut self.mmu);
+                    self.cycles += 16;
+                },
+                I::RETI => {
+                    self.cpu.reti(&mut self.mmu);
+                    self.cycles += 16;
+                },
+                I::RETNZ | I::RETZ | I::RETNC | I::RETC => {
+                    if self.cpu.condition(inst) {
+                        self.cpu.ret(&mut self.mmu);
+                        self.cycles += 20;
+                    } else {
+                        self.cycles += 8;
+                    }
+                },
+                // Extended instructions
+                I::SWAP(reg) | I::RLC(reg) | I::RL(reg) | I::RRC(reg) |
+                I::RR(reg) | I::SLA(reg) | I::SRA(reg) | I::SRL(reg) => {
+                    match inst {
+                        I::SWAP(_) => self.cpu.swap(&mut self.mmu, reg),
+                        I::RLC(_) => self.cpu.rlc(&mut self.mmu, reg),
+                        I::RL(_) => self.cpu.rl(&mut self.mmu, reg),
+                        I::RRC(_) => self.cpu.rrc(&mut self.mmu, reg),
+                        I::RR(_) => self.cpu.rr(&mut self.mmu, reg),
+                        I::SLA(_) => self.cpu.sla(&mut self.mmu, reg),
+                        I::SRA(_) => self.cpu.sra(&mut self.mmu, reg),
+                        _ => self.cpu.srl(&mut self.mmu, reg),
+                    }
+                    self.cycles += if reg == R::HL { 16 } else { 8 };
+                },
                 I::BIT(bit, reg) => {
                     if reg == R::HL {
-                        self.cycles += 16;
+                        self.cycles += 12;
                     } else {
                         self.cycles += 8;
                     }
                     self.cpu.bit_b_r(&mut self.mmu, bit, reg);
                 },
-                I::JRNZ(_) => {
-                    self.cpu.jpnz(data as u8);
-                    self.cycles += 8;
+                I::SET(bit, reg) => {
+                    self.cpu.set_b_r(&mut self.mmu, bit, reg);
+                    self.cycles += if reg == R::HL { 16 } else { 8 };
                 },
+                I::RES(bit, reg) => {
+                    self.cpu.res_b_r(&mut self.mmu, bit, reg);
+                    self.cycles += if reg == R::HL { 16 } else { 8 };
+                },
+                I::ILLEGAL(opcode) => {
+                    self.cpu.undef();
+                    self.cycles += 4;
+                    if let Some(ref mut callback) = self.lockup_callback {
+                        callback(opcode, inst_off);
+                    }
+                },
+                // Operand combinations without an opcode
                 _ => {
-                    println!("{:#?}", self.cpu);
-                    println!("Instruction : {:#?}", inst);
-                    unimplemented!()
-                }
+                    let opcode = self.mmu.peek(inst_off);
+                    return Err(RgbError::IllegalOpcode { opcode, addr: inst_off });
+                },
             }
             // End Execute
+            Ok(())
+        }
+    }
+
+    impl Drop for EmulatorContext {
+        fn drop(&mut self) {
+            if let Err(err) = self.flush_save() {
+                if let Some(ref mut callback) = self.save_error_callback {
+                    callback(&err);
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Runs `program` from 0x0100 one instruction at a time, checking the
+        /// clock cycles each one takes. The instructions are listed with
+        /// their bytes & cycles in the order they're executed.
+        fn check_cycles(program: &[(&[u8], u64)]) {
+            let mut rom = vec![0; 0x8000];
+            let mut addr = 0x0100;
+            for &(bytes, _) in program {
+                rom[addr..addr + bytes.len()].copy_from_slice(bytes);
+                addr += bytes.len();
+            }
+            // the subroutine for CALL
+            rom[0x0200] = 0xC9;
+
+            let mut ctx = new();
+            ctx.load_bytes(&rom).unwrap();
+            ctx.skip_boot_rom();
+            for &(bytes, cycles) in program {
+                let start = ctx.cycles;
+                ctx.step().unwrap();
+                assert_eq!(ctx.cycles - start, cycles, "{:02X?}", bytes);
+            }
+        }
+
+        #[test]
+        fn instruction_cycles() {
+            check_cycles(&[
+                (&[0x00], 4),                   // NOP
+                (&[0x01, 0x34, 0x12], 12),      // LD BC,0x1234
+                (&[0x21, 0x00, 0xC0], 12),      // LD HL,0xC000
+                (&[0x36, 0x42], 12),            // LD (HL),0x42
+                (&[0x7E], 8),                   // LD A,(HL)
+                (&[0x34], 12),                  // INC (HL)
+                (&[0x03], 8),                   // INC BC
+                (&[0x09], 8),                   // ADD HL,BC
+                (&[0xEA, 0x00, 0xC1], 16),      // LD (0xC100),A
+                (&[0xE0, 0x80], 12),            // LDH (0x80),A
+                (&[0xC5], 16),                  // PUSH BC
+                (&[0xC1], 12),                  // POP BC
+                (&[0xE8, 0x01], 16),            // ADD SP,1
+                (&[0xF8, 0xFF], 12),            // LD HL,SP-1
+                (&[0xAF], 4),                   // XOR A
+                (&[0x20, 0x00], 8),             // JR NZ (not taken)
+                (&[0x28, 0x00], 12),            // JR Z (taken)
+                (&[0xC4, 0x00, 0x02], 12),      // CALL NZ (not taken)
+                (&[0xCD, 0x00, 0x02], 24),      // CALL 0x0200
+                (&[], 16),                      // RET
+                (&[0xC0], 8),                   // RET NZ (not taken)
+                (&[0xC2, 0x00, 0x00], 12),      // JP NZ (not taken)
+                (&[0xE9], 4),                   // JP HL
+            ]);
+        }
+
+        #[test]
+        fn extended_instruction_cycles() {
+            check_cycles(&[
+                (&[0x21, 0x00, 0xC0], 12),      // LD HL,0xC000
+                (&[0xCB, 0x11], 8),             // RL C
+                (&[0xCB, 0x7F], 8),             // BIT 7,A
+                (&[0xCB, 0x46], 12),            // BIT 0,(HL)
+                (&[0xCB, 0xC6], 16),            // SET 0,(HL)
+                (&[0xCB, 0x86], 16),            // RES 0,(HL)
+                (&[0xCB, 0x36], 16),            // SWAP (HL)
+            ]);
+        }
+
+        #[test]
+        fn loading_bytes_keeps_the_previous_save() {
+            let dir = std::env::temp_dir().join(format!("rgb_emu_save_{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let rom_path = dir.join("battery.gb");
+            let save_path = dir.join("battery.sav");
+
+            // MBC1 + RAM + battery with 8KB of RAM
+            let mut rom = vec![0; 0x8000];
+            rom[0x0147] = 0x03;
+            rom[0x0149] = 0x02;
+            rom[0x014D] = 0xE2;
+            fs::write(&rom_path, &rom).unwrap();
+            let save = vec![0x42; 0x2000];
+            fs::write(&save_path, &save).unwrap();
+
+            let mut ctx = new();
+            ctx.load_file(&rom_path).unwrap();
+            ctx.load_bytes(&[0; 0x8000]).unwrap();
+            for _ in 0..SAVE_INTERVAL / 4 + 1 {
+                ctx.step().unwrap();
+            }
+            drop(ctx);
+            assert_eq!(fs::read(&save_path).unwrap(), save);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn save_errors_on_drop_go_to_the_callback() {
+            use std::cell::Cell;
+            use std::rc::Rc;
+
+            let dir = std::env::temp_dir().join(format!("rgb_emu_drop_{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let rom_path = dir.join("battery.gb");
+            let mut rom = vec![0; 0x8000];
+            rom[0x0147] = 0x03;
+            rom[0x0149] = 0x02;
+            rom[0x014D] = 0xE2;
+            fs::write(&rom_path, &rom).unwrap();
+
+            let failed = Rc::new(Cell::new(false));
+            let mut ctx = new();
+            ctx.load_file(&rom_path).unwrap();
+            let flag = failed.clone();
+            ctx.set_save_error_callback(move |err| {
+                assert!(matches!(*err, RgbError::Save(_)));
+                flag.set(true);
+            });
+            // change the RAM, then make the save file unwritable
+            ctx.poke_ram(0, 0xA000, 0x42);
+            fs::create_dir_all(dir.join("battery.sav")).unwrap();
+            drop(ctx);
+            assert!(failed.get());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn reset_ends_lockup() {
+            let mut rom = vec![0; 0x8000];
+            rom[0x0100] = 0xD3;
+            let mut ctx = new();
+            ctx.load_bytes(&rom).unwrap();
+            ctx.skip_boot_rom();
+            ctx.step().unwrap();
+            assert!(ctx.is_locked());
+
+            ctx.reset();
+            assert!(!ctx.is_locked());
+            assert_eq!(ctx.cpu.get_pc(), 0x0000);
+            // the boot ROM is mapped again over the cartridge
+            assert_eq!(ctx.peek(0x0000), 0x31);
+            assert_eq!(ctx.peek(0x0100), 0xD3);
+            ctx.step().unwrap();
+            assert_eq!(ctx.cpu.get_pc(), 0x0003);
+        }
+
+        #[test]
+        fn post_boot_io_depends_on_the_model() {
+            let mut ctx = new();
+            ctx.load_bytes(&[0; 0x8000]).unwrap();
+            ctx.skip_boot_rom();
+            assert_eq!(ctx.peek(0xFF40), 0x91);
+            assert_eq!(ctx.peek(0xFF47), 0xFC);
+            assert_eq!(ctx.peek(0xFF00), 0xCF);
+            assert_eq!(ctx.peek(0xFF26), 0xF1);
+
+            // The SGB boot ROM doesn't play the sound
+            let mut ctx = new();
+            ctx.load_bytes(&[0; 0x8000]).unwrap();
+            ctx.set_model(Model::SGB);
+            ctx.skip_boot_rom();
+            assert_eq!(ctx.peek(0xFF40), 0x91);
+            assert_eq!(ctx.peek(0xFF47), 0xFC);
+            assert_eq!(ctx.peek(0xFF00), 0xFF);
+            assert_eq!(ctx.peek(0xFF26), 0xF0);
         }
     }
 }
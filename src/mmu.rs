@@ -4,8 +4,55 @@
 
 use std::fs::File;
 use std::io::Read;
+use std::mem;
 
-use cpu::CPU;
+use apu::APU;
+use boot::{self, Model, DMG_BOOT_ROM};
+use cartridge::{self, Cartridge, RomOnly, ram_bank_offset, rom_bank_offset};
+use dma::{Bus, Dma};
+use header::{CartridgeHeader, CgbSupport, HeaderError};
+use interrupt::{Interrupt, InterruptController};
+use joypad::{Button, Joypad};
+use ppu::PPU;
+use rgb_error::RgbError;
+use serial::Serial;
+use timer::Timer;
+
+/// Byte & word access to the memory map. Words are little endian: the low
+/// byte lives at `addr` and the high byte at `addr + 1`, wrapping at 0xFFFF.
+/// Every byte read or written takes one M-cycle, during which the rest of the
+/// machine keeps running.
+pub trait MemoryBus {
+    /// Reads the byte at `addr`
+    fn rb(&mut self, addr: u16) -> u8;
+
+    /// Writes `val` to `addr`
+    fn wb(&mut self, addr: u16, val: u8);
+
+    /// Spends an M-cycle without accessing memory
+    fn idle(&mut self) { }
+
+    /// Reads the byte at `addr` for debugging; without side effects, timing
+    /// or access restrictions
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Writes `val` to `addr` for debugging; sets the backing state without
+    /// side effects, timing or access restrictions
+    fn poke(&mut self, addr: u16, val: u8);
+
+    /// Reads the word at `addr`
+    fn rw(&mut self, addr: u16) -> u16 {
+        let lo = self.rb(addr) as u16;
+        let hi = self.rb(addr.wrapping_add(1)) as u16;
+        hi << 8 | lo
+    }
+
+    /// Writes the word `val` to `addr`; low byte first
+    fn ww(&mut self, addr: u16, val: u16) {
+        self.wb(addr, val as u8);
+        self.wb(addr.wrapping_add(1), (val >> 8) as u8);
+    }
+}
 
 /// Documentation
 /// -------------
@@ -79,259 +126,622 @@ use cpu::CPU;
 ///                   bytes of a cartridge except for two checksum bytes and
 ///                   taking two lower bytes of the result.
 ///                   (The GB ignores this value)
-
 pub struct MMU {
-    bios: [u8; 256],
-    rom: Vec<u8>,
-    cartridge_type: Option<u8>,
-    mbc: u32, // TODO: ???
-    rom_offset: u16,
-    ram_offset: u16,
+    bios: Vec<u8>, // boot rom => [0000 -> 00FF] (& [0200 -> 08FF] on CGB)
+    cartridge: Box<dyn Cartridge>, // ROM [0000 -> 7FFF] & external RAM [A000 -> BFFF]
+    header: Option<CartridgeHeader>,
     fbuf: [u8; 0x2000], // graphics buffer
-    eram: [u8; 0x2000], // (E)xternal ram => [A000 -> BFFF]
+    oam: [u8; 0xA0], // (O)bject (A)ttribute (M)emory => [FE00 -> FE9F]
     wram: [u8; 0x2000], // internal (W)ork ram => [C000 -> DFFF]
     zram: [u8; 0x100], // (Z)ero page ram => [ff80 -> ffff]
-    in_bios: bool,
-    in_e: bool, // TODO: ???
-    int_flag: bool,
+    in_bios: bool, // boot rom is mapped, until written to FF50
+    model: Model,
+    int: InterruptController, // IE [FFFF] & IF [FF0F]
+    ppu: PPU, // LCD registers [FF40 -> FF4B]
+    timer: Timer, // DIV, TIMA, TMA & TAC [FF04 -> FF07]
+    joypad: Joypad, // P1 [FF00]
+    serial: Serial, // SB & SC [FF01 -> FF02]
+    apu: APU, // sound registers & wave RAM [FF10 -> FF3F]
+    dma: Dma, // OAM DMA [FF46]
+    cgb_mode: bool,
+    double_speed: bool, // KEY1 [FF4D] bit 7
+    speed_switch_armed: bool, // KEY1 [FF4D] bit 0
+    bus_cycles: u32, // cycles the CPU's memory accesses advanced the machine by
 }
 
 impl MMU {
     pub fn new() -> MMU {
         MMU {
-        bios: [
-                0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32,
-                0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
-                0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3,
-                0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
-                0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1A,
-                0xCD, 0x95, 0x00, 0xCD, 0x96, 0x00, 0x13, 0x7B,
-                0xFE, 0x34, 0x20, 0xF3, 0x11, 0xD8, 0x00, 0x06,
-                0x08, 0x1A, 0x13, 0x22, 0x23, 0x05, 0x20, 0xF9,
-                0x3E, 0x19, 0xEA, 0x10, 0x99, 0x21, 0x2F, 0x99,
-                0x0E, 0x0C, 0x3D, 0x28, 0x08, 0x32, 0x0D, 0x20,
-                0xF9, 0x2E, 0x0F, 0x18, 0xF3, 0x67, 0x3E, 0x64,
-                0x57, 0xE0, 0x42, 0x3E, 0x91, 0xE0, 0x40, 0x04,
-                0x1E, 0x02, 0x0E, 0x0C, 0xF0, 0x44, 0xFE, 0x90,
-                0x20, 0xFA, 0x0D, 0x20, 0xF7, 0x1D, 0x20, 0xF2,
-                0x0E, 0x13, 0x24, 0x7C, 0x1E, 0x83, 0xFE, 0x62,
-                0x28, 0x06, 0x1E, 0xC1, 0xFE, 0x64, 0x20, 0x06,
-                0x7B, 0xE2, 0x0C, 0x3E, 0x87, 0xF2, 0xF0, 0x42,
-                0x90, 0xE0, 0x42, 0x15, 0x20, 0xD2, 0x05, 0x20,
-                0x4F, 0x16, 0x20, 0x18, 0xCB, 0x4F, 0x06, 0x04,
-                0xC5, 0xCB, 0x11, 0x17, 0xC1, 0xCB, 0x11, 0x17,
-                0x05, 0x20, 0xF5, 0x22, 0x23, 0x22, 0x23, 0xC9,
-            // start: Scrolling Nintendo graphic
-                0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B,
-                0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
-                0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
-                0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
-                0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
-                0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
-            // end: Scrolling Nintendo graphic
-                0x3c, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x4C,
-                0x21, 0x04, 0x01, 0x11, 0xA8, 0x00, 0x1A, 0x13,
-                0xBE, 0x20, 0xFE, 0x23, 0x7D, 0xFE, 0x34, 0x20,
-                0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20,
-                0xFB, 0x86, 0x20, 0xFE, 0x3E, 0x01, 0xE0, 0x50
-              ],
-            rom: Vec::<u8>::new(),
-            cartridge_type: None,
-            mbc: 0, // TODO: ???
-            rom_offset: 0x0, // 0x4000,
-            ram_offset: 0x0,
+            bios: DMG_BOOT_ROM.to_vec(),
+            cartridge: Box::new(RomOnly::new(Vec::new(), 0)),
+            header: None,
             fbuf: [0; 0x2000],
-            eram: [0; 0x2000],
+            oam: [0; 0xA0],
             wram: [0; 0x2000],
             zram: [0; 0x100],
             in_bios: true,
-            in_e: false,
-            int_flag: false,
+            model: Model::DMG,
+            int: InterruptController::new(),
+            ppu: PPU::new(),
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            apu: APU::new(),
+            dma: Dma::new(),
+            cgb_mode: false,
+            double_speed: false,
+            speed_switch_armed: false,
+            bus_cycles: 0,
         }
     }
 
-    pub fn load_file(&mut self, file: File) {
-        self.rom = file.bytes()
-                       .map(|r: Result<u8, _>| r.unwrap())
-                       .collect();
-        self.cartridge_type = match self.rom.get(0x0147) {
-            Some(val) => Some(*val),
-            None => None,
-        }
+    pub fn load_file(&mut self, mut file: File) -> Result<(), RgbError> {
+        let mut rom = Vec::new();
+        file.read_to_end(&mut rom)?;
+        self.load_rom(rom)
     }
 
+    /// Loads a cartridge, creating the MBC described by its header
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), RgbError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        self.cartridge = cartridge::from_header(rom, &header)?;
+        self.header = Some(header);
+        self.update_cgb_mode();
+        Ok(())
+    }
+
+    /// Returns the header of the loaded cartridge
+    pub fn header(&self) -> Option<&CartridgeHeader> {
+        self.header.as_ref()
+    }
+
+    /// Returns if the cartridge has battery backed RAM (or clock)
+    pub fn has_battery(&self) -> bool {
+        self.header.as_ref().is_some_and(|header| header.cartridge_type.has_battery())
+    }
+
+    /// Returns the cartridge
+    pub fn cartridge(&self) -> &dyn Cartridge {
+        &*self.cartridge
+    }
+
+    /// Returns the cartridge mutably
+    pub fn cartridge_mut(&mut self) -> &mut dyn Cartridge {
+        &mut *self.cartridge
+    }
+
+    /// Loads raw bytes as a cartridge without a header or MBC
     pub fn load_bytes(&mut self, rom: &[u8]) {
-        self.rom = rom.to_owned();
+        self.cartridge = Box::new(RomOnly::new(rom.to_owned(), 0));
+        self.header = None;
+        self.update_cgb_mode();
     }
 
-    // Should only be used for debugging memory address;
-    // for getting the next program byte use `CPU.p_fetch()` instead.
-    pub fn get_byte_at_offset(&self) -> u8 {
-        *self.rom.get(self.rom_offset as usize).unwrap()
+    /// Returns the interrupt controller so components can raise their
+    /// interrupt lines and the CPU can service them.
+    pub fn interrupts(&mut self) -> &mut InterruptController {
+        &mut self.int
     }
 
-    pub fn get_offset(&self) -> u16 {
-        self.rom_offset
+    /// Raises the interrupt line of `int`
+    pub fn request_interrupt(&mut self, int: Interrupt) {
+        self.int.request(int);
     }
 
-    pub fn incr_rom(&mut self) {
-        self.rom_offset += 1;
+    /// Advances the memory mapped components by `cycles` CPU clock cycles
+    pub fn tick(&mut self, cycles: u32) {
+        // In double speed mode the CPU runs twice as fast as the PPU
+        let dots = if self.double_speed { cycles / 2 } else { cycles };
+        self.ppu.step(dots, &self.fbuf, &self.oam, &mut self.int);
+        self.apu.step(dots, self.apu_div());
+        self.cartridge.tick(dots);
+        self.timer.step(cycles, &mut self.int);
+        self.serial.step(cycles, &mut self.int);
+        self.step_dma(cycles);
     }
 
-    /// Read byte
-    pub fn rb(&self, cpu: &CPU, addr: u16) -> u8 {
-        match addr & 0xF000 {
-            // Rom bank 0
-            0x0000 ... 0x0fff => {
-                // TODO add bios maybe?
-                return *self.rom.get(addr as usize).unwrap();
+    /// Copies the bytes of an OAM DMA transfer due in `cycles` clock cycles.
+    /// The DMA runs at the speed of the CPU, also in double speed mode.
+    fn step_dma(&mut self, cycles: u32) {
+        for _ in 0..self.dma.clock(cycles) {
+            if let Some((source, index)) = self.dma.next_transfer() {
+                // The DMA sees [E000 -> FFFF] as an echo of work RAM
+                let addr = if source >= 0xE000 { source - 0x2000 } else { source };
+                let byte = self.peek(addr);
+                self.oam[index] = byte;
+                self.dma.set_byte(byte);
+            }
+        }
+    }
+
+    /// Returns what the CPU reads from `addr` while it conflicts with a
+    /// running OAM DMA, or None if the CPU can access it.
+    fn dma_conflict(&self, addr: u16) -> Option<u8> {
+        if !self.dma.active() {
+            return None;
+        }
+        match Bus::of(addr) {
+            Bus::Internal if (0xFE00..0xFEA0).contains(&addr) => Some(0xFF),
+            Bus::Internal => None,
+            bus if bus == self.dma.bus() => Some(self.dma.byte()),
+            _ => None,
+        }
+    }
+
+    /// Holds down a button of the joypad
+    pub fn press_button(&mut self, button: Button) {
+        self.joypad.press(button, &mut self.int);
+    }
+
+    /// Returns if a pressed button pulls one of the selected joypad lines
+    /// (P10-P13) low, which wakes the CPU from `STOP`
+    pub fn joypad_line_low(&self) -> bool {
+        self.joypad.lines() != 0
+    }
+
+    /// Lets go of a button of the joypad
+    pub fn release_button(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    /// Returns the APU mutably
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    /// Returns the serial port mutably
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    /// Clears the timer's divider; done by the `STOP` instruction
+    pub fn reset_div(&mut self) {
+        self.apu.reset_div(self.apu_div());
+        self.timer.reset_div();
+    }
+
+    /// Returns the timer's divider as seen by the APU, which counts at normal
+    /// speed also in double speed mode
+    fn apu_div(&self) -> u16 {
+        self.timer.div() >> self.double_speed as u8
+    }
+
+    /// Returns the PPU
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    /// Returns the PPU mutably
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        &mut self.ppu
+    }
+
+    /// Returns the emulated Game Boy model
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Selects the emulated Game Boy model
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.ppu.set_cgb_hardware(model.is_cgb());
+        self.update_cgb_mode();
+    }
+
+    /// Loads a boot rom dump, selecting the model it belongs to
+    pub fn load_boot_rom(&mut self, rom: Vec<u8>) -> Result<Model, RgbError> {
+        let model = Model::from_boot_rom(&rom)?;
+        self.bios = rom;
+        self.in_bios = true;
+        self.set_model(model);
+        Ok(model)
+    }
+
+    /// Turns the machine off and on again. The boot rom is mapped again while
+    /// the cartridge, the model, the link cable & the sample rate are kept.
+    pub fn reset(&mut self) {
+        let mut mmu = MMU::new();
+        mem::swap(&mut mmu.bios, &mut self.bios);
+        mem::swap(&mut mmu.cartridge, &mut self.cartridge);
+        mmu.header = self.header.take();
+        if let Some(link) = self.serial.disconnect() {
+            mmu.serial.connect(link);
+        }
+        mmu.apu.set_sample_rate(self.apu.sample_rate());
+        mmu.set_model(self.model);
+        *self = mmu;
+    }
+
+    /// Unmaps the boot rom and sets up the I/O registers it leaves behind
+    pub fn skip_boot_rom(&mut self) {
+        self.in_bios = false;
+        for &(addr, val) in self.model.post_boot_io() {
+            self.poke(addr, val);
+        }
+        self.apu.skip_boot();
+        self.timer.set_div(self.model.post_boot_div());
+        self.ppu.skip_boot();
+    }
+
+    /// A CGB runs cartridges with CGB support in color mode
+    fn update_cgb_mode(&mut self) {
+        let cgb_cartridge = self.header.as_ref()
+            .is_some_and(|header| header.cgb != CgbSupport::None);
+        let cgb_mode = self.model.is_cgb() && cgb_cartridge;
+        self.set_cgb_mode(cgb_mode);
+    }
+
+    /// Returns if the CGB only registers are enabled
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    /// Enables the CGB only registers
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+        self.serial.set_cgb_mode(cgb_mode);
+    }
+
+    /// Returns if a `STOP` should perform a CGB speed switch
+    pub fn speed_switch_armed(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    /// Returns if the CGB is running in double speed mode
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Toggles between normal & double speed mode; done by a `STOP` while a
+    /// speed switch is armed in KEY1.
+    pub fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+    }
+
+    /// Reads the KEY1 register; bit 7 is the current speed and bit 0 is set
+    /// when a speed switch is armed. Reads 0xFF on non CGB hardware.
+    fn read_key1(&self) -> u8 {
+        if !self.cgb_mode {
+            return 0xFF;
+        }
+        (self.double_speed as u8) << 7 | 0x7E | self.speed_switch_armed as u8
+    }
+
+    /// Reads the unusable region [FEA0 -> FEFF]. The DMG family reads 0x00
+    /// (0xFF while the PPU owns OAM), the CGB repeats the upper nibble of the
+    /// lower address byte.
+    fn read_unusable(&self, addr: u16) -> u8 {
+        if !self.model.is_cgb() && !self.ppu.oam_accessible() {
+            0xFF
+        } else {
+            self.peek_unusable(addr)
+        }
+    }
+
+    /// Returns the unusable region [FEA0 -> FEFF] while OAM is accessible
+    fn peek_unusable(&self, addr: u16) -> u8 {
+        if self.model.is_cgb() {
+            let nibble = (addr & 0xF0) as u8;
+            nibble | nibble >> 4
+        } else {
+            0x00
+        }
+    }
+
+    /// Returns the offset of `addr` in the boot rom while it's mapped over the
+    /// cartridge (not over the cartridge header on the CGB)
+    fn bios_offset(&self, addr: u16) -> Option<usize> {
+        let offset = addr as usize;
+        if self.in_bios && (offset < 0x100 || (offset >= 0x200 && offset < self.bios.len())) {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the offset into the external RAM of `addr` in bank `bank`
+    fn ram_offset(&self, bank: usize, addr: u16) -> Option<usize> {
+        cartridge::ram_offset(self.cartridge.ram(), bank, addr)
+    }
+
+    /// Reads `addr` of ROM bank `bank` for debugging; both [0000 -> 3FFF] and
+    /// [4000 -> 7FFF] address the start of the bank.
+    pub fn peek_rom(&self, bank: usize, addr: u16) -> u8 {
+        let rom = self.cartridge.rom();
+        cartridge::rom_byte(rom, rom_bank_offset(rom, bank) + (addr & 0x3FFF) as usize)
+    }
+
+    /// Patches `addr` of ROM bank `bank` for debugging
+    pub fn poke_rom(&mut self, bank: usize, addr: u16, val: u8) {
+        let offset = rom_bank_offset(self.cartridge.rom(), bank) + (addr & 0x3FFF) as usize;
+        if let Some(byte) = self.cartridge.rom_mut().get_mut(offset) {
+            *byte = val;
+        }
+    }
+
+    /// Reads `addr` of external RAM bank `bank` for debugging, also while the
+    /// RAM is disabled
+    pub fn peek_ram(&self, bank: usize, addr: u16) -> u8 {
+        match self.ram_offset(bank, addr) {
+            Some(offset) => self.cartridge.ram()[offset],
+            None => 0xFF,
+        }
+    }
+
+    /// Writes `addr` of external RAM bank `bank` for debugging, also while
+    /// the RAM is disabled
+    pub fn poke_ram(&mut self, bank: usize, addr: u16, val: u8) {
+        if let Some(offset) = self.ram_offset(bank, addr) {
+            self.cartridge.ram_mut()[offset] = val;
+        }
+    }
+
+    /// Sets an I/O register [FF00 -> FF7F] without the side effects of a write
+    fn poke_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF00 => self.joypad.poke(val),
+            0xFF01 ..= 0xFF02 => self.serial.poke(addr, val),
+            0xFF04 ..= 0xFF07 => self.timer.poke(addr, val),
+            0xFF0F => self.int.write_if(val),
+            0xFF10 ..= 0xFF3F => self.apu.poke(addr, val),
+            0xFF46 => self.dma.poke(val),
+            0xFF40 ..= 0xFF4B => self.ppu.poke(addr, val),
+            0xFF4D if self.cgb_mode => {
+                self.double_speed = val & 0x80 != 0;
+                self.speed_switch_armed = val & 0x01 != 0;
             },
-            0x1000 ... 0x3000 => {
-                return *self.rom.get(addr as usize).unwrap();
+            // The boot rom can be mapped back in
+            0xFF50 => { self.in_bios = val & 0x01 == 0; },
+            _ => { },
+        }
+    }
+
+    /// Reads an I/O register [FF00 -> FF7F]; unmapped registers read 0xFF
+    fn read_io(&self, addr: u16) -> u8 {
+        match addr {
+            // Joypad (P1)
+            0xFF00 => self.joypad.rb(),
+            // Serial transfer
+            0xFF01 ..= 0xFF02 => self.serial.rb(addr),
+            // Timer
+            0xFF04 ..= 0xFF07 => self.timer.rb(addr),
+            // Interrupt flags
+            0xFF0F => self.int.read_if(),
+            // Sound
+            0xFF10 ..= 0xFF3F => self.apu.rb(addr),
+            // OAM DMA
+            0xFF46 => self.dma.read(),
+            // GPU read-byte
+            0xFF40 ..= 0xFF4B => self.ppu.rb(addr),
+            // CGB speed switch (KEY1)
+            0xFF4D => self.read_key1(),
+            // Unmapped & write only registers, e.g. the boot rom disable
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes an I/O register [FF00 -> FF7F]; writes to unmapped registers
+    /// are ignored.
+    fn write_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            // Joypad (P1)
+            0xFF00 => self.joypad.wb(val, &mut self.int),
+            // Serial transfer
+            0xFF01 ..= 0xFF02 => self.serial.wb(addr, val),
+            // Timer
+            0xFF04 => self.reset_div(),
+            0xFF05 ..= 0xFF07 => self.timer.wb(addr, val),
+            // Interrupt flags
+            0xFF0F => self.int.write_if(val),
+            // Sound
+            0xFF10 ..= 0xFF3F => self.apu.wb(addr, val),
+            // OAM DMA
+            0xFF46 => self.dma.write(val),
+            // GPU write-byte
+            0xFF40 ..= 0xFF4B => self.ppu.wb(addr, val),
+            // CGB speed switch (KEY1)
+            0xFF4D => {
+                self.speed_switch_armed = self.cgb_mode && val & 0x01 != 0;
             },
-            // Rom bank 1
-            0x4000 ... 0x7000 => {
-                let offset = self.rom_offset as usize + (addr & 0x3FFF) as usize;
-                return *self.rom.get(offset).unwrap();
+            // Boot rom disable; can't be mapped back in
+            0xFF50 if val & 0x01 != 0 => { self.in_bios = false; },
+            _ => { },
+        }
+    }
+
+    /// Reads a byte as the CPU sees it, without timing
+    fn read(&self, addr: u16) -> u8 {
+        if let Some(val) = self.dma_conflict(addr) {
+            return val;
+        }
+        match addr {
+            // Rom bank 0
+            0x0000 ..= 0x0FFF => {
+                // The boot rom is mapped over the cartridge
+                match self.bios_offset(addr) {
+                    Some(offset) => self.bios[offset],
+                    None => self.cartridge.read_rom(addr),
+                }
             },
+            0x1000 ..= 0x3FFF => self.cartridge.read_rom(addr),
+            // Rom bank 1 (switchable)
+            0x4000 ..= 0x7FFF => self.cartridge.read_rom(addr),
             // Video RAM
-            0x8000 ... 0x9000 => {
-                return *self.fbuf.get((addr & 0x1FFF) as usize).unwrap();
-            },
+            0x8000 ..= 0x9FFF => self.fbuf[(addr & 0x1FFF) as usize],
             // External RAM
-            0xA000 ... 0xB000 => {
-                let offset = self.ram_offset as usize + (addr & 0x1FFF) as usize;
-                return *self.eram.get(offset).unwrap();
-            },
-            // Work RAM & Echo
-            0xC000 ... 0xE000 => {
-                return *self.wram.get((addr & 0x1FFF) as usize).unwrap();
+            0xA000 ..= 0xBFFF => self.cartridge.read_ram(addr),
+            // Work RAM & Echo (a mirror of [C000 -> DDFF])
+            0xC000 ..= 0xFDFF => self.wram[(addr & 0x1FFF) as usize],
+            // OAM
+            0xFE00 ..= 0xFE9F => {
+                // The PPU owns OAM during the OAM scan & drawing
+                if self.ppu.oam_accessible() {
+                    self.oam[(addr & 0xFF) as usize]
+                } else {
+                    0xFF
+                }
             },
-            // Everything Else
-            0xF000 => {
-                match addr & 0x0F00 {
-                    // Echo RAM
-                    0x000 ... 0xD00 => {
-                        return *self.wram.get((addr & 0x1FFF) as usize).unwrap();
-                    },
-                    // OAM
-                    0xE00 => {
-                        unimplemented!();
-                    },
-                    // Zero-page RAM, I/O, Interrupts
-                    0xF00 => {
-                        if addr == 0xFFFF {
-                            return self.in_e as u8;
-                        } else if addr > 0xFF7F {
-                            return *self.zram.get((addr & 0x7F) as usize).unwrap();
-                        }
-                        match addr & 0xF0 {
-                            0x00 => {
-                                match addr & 0xF {
-                                    // Directional Pad
-                                    0x0 => { unimplemented!() },
-                                    // TODO: Timer flags?
-                                    0x4 ... 0x7 => { unimplemented!() },
-                                    0x15 => { return self.int_flag as u8; },
-                                    _ => { return 0u8; },
-                                }
-                            }
-                            0x10 => { return 0u8; },
-                            // GPU read-byte
-                            0x40 ... 0x70 => { unreachable!()},
-                            _ => {unreachable!()},
-                        }
-                    },
-                    _ => {unreachable!()},
+            // Unusable
+            0xFEA0 ..= 0xFEFF => self.read_unusable(addr),
+            // I/O
+            0xFF00 ..= 0xFF7F => self.read_io(addr),
+            // Zero-page RAM
+            0xFF80 ..= 0xFFFE => self.zram[(addr & 0x7F) as usize],
+            // Interrupt enable
+            0xFFFF => self.int.read_ie(),
+        }
+    }
+
+    /// Writes a byte as the CPU does, without timing
+    fn write(&mut self, addr: u16, val: u8) {
+        // Writes conflicting with a DMA transfer are lost
+        if self.dma_conflict(addr).is_some() {
+            return;
+        }
+        match addr {
+            // Rom bank 0 & 1
+            // Writes to the rom control the MBC
+            0x0000 ..= 0x7FFF => self.cartridge.write_rom(addr, val),
+            // Video RAM
+            0x8000 ..= 0x9FFF => self.fbuf[(addr & 0x1FFF) as usize] = val,
+            // External RAM
+            0xA000 ..= 0xBFFF => self.cartridge.write_ram(addr, val),
+            // Work RAM & Echo (a mirror of [C000 -> DDFF])
+            0xC000 ..= 0xFDFF => self.wram[(addr & 0x1FFF) as usize] = val,
+            // OAM (Sprite Attribute Memory)
+            0xFE00 ..= 0xFE9F => {
+                if self.ppu.oam_accessible() {
+                    self.oam[(addr & 0xFF) as usize] = val;
                 }
             },
-            _ => {unreachable!()},
+            // Unusable; writes are ignored
+            0xFEA0 ..= 0xFEFF => { },
+            // I/O
+            0xFF00 ..= 0xFF7F => self.write_io(addr, val),
+            // Zero-page RAM
+            0xFF80 ..= 0xFFFE => self.zram[(addr & 0x7F) as usize] = val,
+            // Interrupt enable
+            0xFFFF => self.int.write_ie(val),
         }
-        // read 8-bit byte from memory
-        unimplemented!();
     }
 
-    /// Reads a word (2-Bytes) from the given address
-    pub fn rw(&mut self, cpu: &CPU, addr: u16) -> u16 {
-        // read 16-bit word from memory
-        let mut val = 0u16;
-        val += self.rb(cpu, addr) as u16;
-        val += (self.rb(cpu, addr+1) as u16) << 8;
+    /// Advances the machine by the M-cycle of a CPU memory access
+    fn bus_cycle(&mut self) {
+        self.tick(4);
+        self.bus_cycles += 4;
+    }
+
+    /// Returns the cycles spent on CPU memory accesses since the last call
+    pub fn take_bus_cycles(&mut self) -> u32 {
+        let cycles = self.bus_cycles;
+        self.bus_cycles = 0;
+        cycles
+    }
+}
+
+impl MemoryBus for MMU {
+    fn rb(&mut self, addr: u16) -> u8 {
+        let val = self.read(addr);
+        self.bus_cycle();
         val
     }
 
-    /// Writes a byte to the given address
-    pub fn wb(&mut self, addr: u16, val: u8) {
-        match addr & 0xF000 {
-            // Rom bank 0
-            0x0000 ... 0x7000 => {
-                unreachable!(); // The rom should not write here...
-            },
-            // Video RAM
-            0x8000 ... 0x9000 => {
-                self.fbuf[(addr & 0x1FFF) as usize] = val;
-            },
-            // External RAM
-            0xA000 ... 0xB000 => {
-                let offset = self.ram_offset as usize + (addr & 0x1FFF) as usize;
-                //self.eram.insert(offset, val);
-            },
-            // Work RAM & Echo
-            0xC000 ... 0xE000 => {
-                //self.wram.insert((addr & 0x1FFF) as usize, val);
+    fn wb(&mut self, addr: u16, val: u8) {
+        self.write(addr, val);
+        self.bus_cycle();
+    }
+
+    fn idle(&mut self) {
+        self.bus_cycle();
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x7FFF => match self.bios_offset(addr) {
+                Some(offset) => self.bios[offset],
+                None => self.cartridge.read_rom(addr),
             },
-            // Everything Else
-            0xF000 => {
-                match addr & 0x0F00 {
-                    // Echo RAM (handeled as if it were the working ram)
-                    0x000 ... 0xD00 => {
-                        //self.wram.insert((addr & 0x1FFF) as usize, val);
-                    },
-                    // OAM (Sprite Attribute Memory)
-                    0xE00 => {
-                        unimplemented!();
-                    },
-                    // Zero-page RAM, I/O, Interrupts
-                    0xF00 => {
-                        if addr == 0xFFFF {
-                            //self.in_e = val;
-                        } else if addr > 0xFF7F {
-                            //self.zram.insert((addr & 0x7F) as usize).unwrap();
-                        }
-                        match addr & 0xF0 {
-                            0x00 => {
-                                match addr & 0xF {
-                                    // Directional Pad
-                                    0x0 => { unimplemented!() },
-                                    // TODO: Timer flags?
-                                    0x4 ... 0x7 => { unimplemented!() },
-                                    0xE => {
-                                        //return self.int_flag as u8;
-                                    },
-                                    0xF => {
-                                        //return 0u8;
-                                    },
-                                    _ => { unreachable!(); }
-                                }
-                            }
-                            0x10 => {
-                                //return 0u8;
-                            },
-                            // GPU read-byte
-                            0x40 ... 0x70 => { unreachable!()},
-                            _ => {unreachable!()},
-                        }
-                    },
-                    _ => {unreachable!()},
-                }
+            0x8000 ..= 0x9FFF => self.fbuf[(addr & 0x1FFF) as usize],
+            0xA000 ..= 0xBFFF => self.cartridge.peek_ram(addr),
+            0xC000 ..= 0xFDFF => self.wram[(addr & 0x1FFF) as usize],
+            0xFE00 ..= 0xFE9F => self.oam[(addr & 0xFF) as usize],
+            0xFEA0 ..= 0xFEFF => self.peek_unusable(addr),
+            0xFF00 ..= 0xFF7F => self.read_io(addr),
+            0xFF80 ..= 0xFFFE => self.zram[(addr & 0x7F) as usize],
+            0xFFFF => self.int.read_ie(),
+        }
+    }
+
+    fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            // Patches the boot rom or the ROM bank mapped at `addr`
+            0x0000 ..= 0x7FFF => match self.bios_offset(addr) {
+                Some(offset) => self.bios[offset] = val,
+                None => {
+                    let bank = self.cartridge.rom_bank(addr);
+                    self.poke_rom(bank, addr, val);
+                },
             },
-            _ => {unreachable!()},
+            0x8000 ..= 0x9FFF => self.fbuf[(addr & 0x1FFF) as usize] = val,
+            0xA000 ..= 0xBFFF => self.cartridge.poke_ram(addr, val),
+            0xC000 ..= 0xFDFF => self.wram[(addr & 0x1FFF) as usize] = val,
+            0xFE00 ..= 0xFE9F => self.oam[(addr & 0xFF) as usize] = val,
+            0xFEA0 ..= 0xFEFF => { },
+            0xFF00 ..= 0xFF7F => self.poke_io(addr, val),
+            0xFF80 ..= 0xFFFE => self.zram[(addr & 0x7F) as usize] = val,
+            0xFFFF => self.int.write_ie(val),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an MMU with the bytes 0x00 - 0x9F at C000, 0xAA in VRAM &
+    /// 0x55 in HRAM, which just started an OAM DMA from C000
+    fn dma_from_wram() -> MMU {
+        let mut mmu = MMU::new();
+        for i in 0..0xA0 {
+            mmu.write(0xC000 + i, i as u8);
+        }
+        mmu.write(0x8000, 0xAA);
+        mmu.write(0xFF80, 0x55);
+        mmu.write(0xFF46, 0xC0);
+        mmu
+    }
+
+    #[test]
+    fn dma_copies_160_bytes() {
+        let mut mmu = dma_from_wram();
+        // startup delay
+        mmu.tick(4);
+        assert!(!mmu.dma.active());
+        mmu.tick(4 * 0x9F);
+        assert!(mmu.dma.active());
+        assert_eq!(mmu.oam[0x9E], 0x9E);
+        assert_eq!(mmu.oam[0x9F], 0x00);
+        mmu.tick(4);
+        assert!(!mmu.dma.active());
+        for i in 0..0xA0 {
+            assert_eq!(mmu.read(0xFE00 + i), i as u8);
         }
     }
 
-    /// Writes a word (2-Bytes) to the given address
-    pub fn ww(&self, cpu: &CPU, addr: u16, val: u16) {
-        // write 16-bit word from memory
-        unimplemented!();
+    #[test]
+    fn dma_locks_oam_and_its_bus() {
+        let mut mmu = dma_from_wram();
+        mmu.tick(4 * 0x11);
+        assert_eq!(mmu.read(0xFE00), 0xFF);
+        // the external bus reads the byte being copied, the others are free
+        assert_eq!(mmu.read(0xC080), 0x0F);
+        assert_eq!(mmu.read(0x0150), 0x0F);
+        assert_eq!(mmu.read(0x8000), 0xAA);
+        assert_eq!(mmu.read(0xFF80), 0x55);
+        // writes to the bus are lost
+        mmu.write(0xC090, 0x42);
+        mmu.tick(4 * 0x90);
+        assert_eq!(mmu.read(0xC090), 0x90);
     }
 }
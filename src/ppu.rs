@@ -0,0 +1,1060 @@
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+
+use interrupt::{Interrupt, InterruptController};
+use screen::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// The PPU draws the screen one line at a time. Every line takes 456 dots
+// (one dot per clock cycle at normal speed) and a frame consists of 154 lines;
+// 144 visible ones followed by 10 lines of vertical blank.
+//
+// While drawing a visible line the PPU cycles through three modes:
+//
+// Mode | Name          | Dots  | Accessible Memory
+// ------------------------------------------------
+//  2   | OAM Scan      |  80   | VRAM
+//  3   | Drawing       | 172+  | -
+//  0   | Horizontal Bl | rest  | VRAM & OAM
+//  1   | Vertical Bl   | 4560  | VRAM & OAM
+//
+// Registers:
+// 0xFF40 : LCDC - LCD Control
+//          7 - LCD Display Enable           (0=Off, 1=On)
+//          6 - Window Tile Map Select       (0=9800-9BFF, 1=9C00-9FFF)
+//          5 - Window Display Enable        (0=Off, 1=On)
+//          4 - BG & Window Tile Data Select (0=8800-97FF, 1=8000-8FFF)
+//          3 - BG Tile Map Select           (0=9800-9BFF, 1=9C00-9FFF)
+//          2 - OBJ (Sprite) Size            (0=8x8, 1=8x16)
+//          1 - OBJ (Sprite) Display Enable  (0=Off, 1=On)
+//          0 - BG Display                   (0=Off, 1=On)
+// 0xFF41 : STAT - LCD Status
+//          6 - LYC=LY Coincidence Interrupt (1=Enable)
+//          5 - Mode 2 OAM Interrupt         (1=Enable)
+//          4 - Mode 1 V-Blank Interrupt     (1=Enable)
+//          3 - Mode 0 H-Blank Interrupt     (1=Enable)
+//          2 - Coincidence Flag             (0:LYC<>LY, 1:LYC=LY) (Read Only)
+//          1-0 - Mode Flag                  (see above)           (Read Only)
+// 0xFF42 : SCY  - Scroll Y
+// 0xFF43 : SCX  - Scroll X
+// 0xFF44 : LY   - LCDC Y-Coordinate (Read Only)
+// 0xFF45 : LYC  - LY Compare
+// 0xFF47 : BGP  - BG Palette Data
+// 0xFF48 : OBP0 - Object Palette 0 Data
+// 0xFF49 : OBP1 - Object Palette 1 Data
+// 0xFF4A : WY   - Window Y Position
+// 0xFF4B : WX   - Window X Position minus 7
+//
+// Drawing (mode 3) is done dot by dot with a pixel FIFO. A fetcher reads the
+// tile number, the low & high bytes of the tile data (2 dots each) and pushes
+// the eight pixels of the tile into the FIFO once it is empty. Every dot one
+// pixel is shifted out of the FIFO, it gets its shade a dot later and reaches
+// the LCD another dot later. This makes the length of mode 3 variable:
+//   - the FIFO starts out with 8 pixels which are thrown away while the first
+//     tile is fetched
+//   - while those are shifted out, a 3 bit counter is compared to the lower
+//     3 bits of SCX; once they match the next 8 pixels are thrown away. The
+//     pixels of the first tile which are left over are therefore discarded
+//     (0 - 7 dots). If the counter wraps around without a match (SCX changed
+//     in the meantime) the FIFO & the fetcher start over.
+//   - starting the window clears the FIFO & restarts the fetcher (6 dots)
+// Registers are read at the moment they are used so that writes during mode 3
+// take effect in the middle of the line: SCX & SCY when fetching a tile (the
+// tile column follows SCX & the pixel being shifted out) and BGP when a pixel
+// reaches the LCD.
+//
+// Sprites (objects) are described by 40 entries of 4 bytes in OAM:
+//   Byte 0 - Y Position (minus 16)
+//   Byte 1 - X Position (minus 8)
+//   Byte 2 - Tile Number (bit 0 is ignored for 8x16 sprites)
+//   Byte 3 - Flags
+//            7 - BG & Window over OBJ (0=No, 1=BG colors 1-3 over OBJ)
+//            6 - Y flip
+//            5 - X flip
+//            4 - Palette (0=OBP0, 1=OBP1)
+// The OAM scan (mode 2) selects the first 10 sprites in OAM that are on the
+// current line. During mode 3, once the first pixel of a selected sprite is
+// about to reach the LCD, the PPU stops shifting out pixels, waits for the
+// background fetcher to finish the current tile (0 - 5 dots) and then fetches
+// the sprite (6 dots). Sprites off the left edge of the screen are already
+// reached while the first tile is thrown away.
+// The sprite's pixels are merged into the sprite FIFO where they only replace
+// transparent pixels; as sprites are fetched in order of their x coordinate
+// (and OAM index for equal x) this gives the DMG sprite priorities.
+//
+// Turning the LCD off (LCDC bit 7) resets LY & the mode to 0 and the screen
+// shows white. Turning it back on starts a special first line: it is 4 dots
+// shorter and stays in mode 0 instead of doing an OAM scan (so there is no
+// mode 2 STAT interrupt), and the first frame isn't shown on the LCD. Real
+// hardware should only be turned off during V-Blank, doing it at another time
+// can damage the LCD.
+//
+// The four STAT interrupt sources (mode 0, 1 & 2 and LY=LYC) are OR-ed into a
+// single interrupt line and an interrupt is only requested when that line goes
+// from low to high. While one source holds the line high, another source
+// becoming active doesn't request a new interrupt ("STAT blocking").
+// LY is compared to LYC every dot, except for the first 4 dots of a line where
+// the comparison (and the coincidence flag) reads as 0 on the new LY. On line
+// 153 LY already reads 0 after those 4 dots; LYC=153 matches for the next 4
+// dots and then LYC=0 matches for the rest of line 153 and during line 0.
+// On the DMG writing STAT enables all sources for one cycle, so a write during
+// mode 0, mode 1 or with LY=LYC requests an interrupt if the line was low.
+
+/// Number of dots in a single line
+const LINE_DOTS: u32 = 456;
+/// Number of lines in a frame, including the vertical blank
+const FRAME_LINES: u8 = 154;
+/// Length of the OAM scan (mode 2)
+const OAM_SCAN_DOTS: u32 = 80;
+/// Dots at the start of a line before LY is compared to LYC
+const LYC_DELAY: u32 = 4;
+/// Dots at the start of a line before the mode switches
+const MODE_DELAY: u32 = 4;
+/// Dot near the end of a line at which WY is compared to LY
+const WINDOW_Y_DOT: u32 = 454;
+/// Dots missing from the first line after the LCD is turned on
+const FIRST_LINE_SKIP: u32 = 4;
+/// Dots at the start of mode 3 before the fetcher starts
+const STARTUP_DOTS: u8 = 2;
+/// Position of the first pixel shifted out in mode 3, the pixels left of 0
+/// are thrown away
+const FIRST_POSITION: i16 = -16;
+/// Dots taken by fetching the tile data of a sprite
+const SPRITE_FETCH_DOTS: u8 = 6;
+/// Pixels before the end of mode 3 from which STAT reads mode 0
+const HBLANK_READ_EARLY: u8 = 3;
+/// Pixels before the end of mode 3 at which the mode 0 interrupt is requested
+const HBLANK_IRQ_EARLY: u8 = 1;
+/// Maximum number of sprites drawn on a single line
+const SPRITES_PER_LINE: usize = 10;
+
+/// The modes of the PPU as reported in the lower two bits of STAT
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// The steps of the background & window fetcher; each takes 2 dots except
+/// `Push` which is retried every dot until the FIFO is empty.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+/// A sprite selected by the OAM scan
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    flags: u8,
+    fetched: bool,
+}
+
+/// A pixel in the sprite FIFO
+#[derive(Debug, Clone, Copy)]
+struct SpritePixel {
+    color: u8,
+    palette: bool, // OBP1 if set
+    bg_priority: bool,
+}
+
+/// State of the background & window fetcher
+struct Fetcher {
+    step: FetchStep,
+    dots: u8,
+    tile_x: u8, // tile column relative to the start of the line or window
+    tile_num: u8,
+    lo: u8,
+    hi: u8,
+}
+
+impl Fetcher {
+    fn new() -> Fetcher {
+        Fetcher {
+            step: FetchStep::Tile,
+            dots: 0,
+            tile_x: 0,
+            tile_num: 0,
+            lo: 0,
+            hi: 0,
+        }
+    }
+}
+
+pub struct PPU {
+    lcdc: u8,
+    stat: u8, // only the interrupt enable bits (3 - 6)
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    dot: u32, // dot within the current line
+    window_line: u8, // internal line counter of the window
+    window_y_hit: bool, // WY matched LY during this frame
+    window_active: bool, // the window is being drawn on this line
+    lx: u8, // x coordinate of the next pixel sent to the LCD
+    position: i16, // x coordinate of the next pixel shifted out of the FIFO
+    shifted: Option<u8>, // pixel shifted out during the previous dot
+    lcd_pixel: Option<(u8, u8)>, // color & background shade of the pixel sent to the LCD next
+    startup: u8, // dots left before the fetcher starts
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<u8>, // color numbers of the background & window
+    sprites: Vec<Sprite>, // sprites on the current line
+    sprite_fetch: Option<usize>, // the sprite being fetched
+    sprite_dots: u8, // dots left of the sprite fetch
+    sprite_tile: Option<u8>, // background tile the fetcher finished for a sprite
+    obj_enable: bool, // LCDC bit 1 during the previous dot
+    obj_fifo: VecDeque<SpritePixel>,
+    frame: Vec<u8>, // shades (0 - 3) of the last drawn frame
+    frame_ready: bool,
+    unsafe_lcd_off: bool, // LCD was turned off outside of V-Blank
+    first_line: bool, // on the special first line after turning the LCD on
+    skip_frame: bool, // the first frame after turning the LCD on isn't shown
+    lyc_match: bool, // LY=LYC coincidence as seen by STAT
+    stat_line: bool, // combined STAT interrupt line
+    stat_write: bool, // STAT was written, all sources are enabled for a dot
+    cgb_hardware: bool, // running on a CGB, even in DMG mode
+}
+
+impl PPU {
+    pub fn new() -> PPU {
+        PPU {
+            lcdc: 0x00,
+            stat: 0x00,
+            scy: 0x00,
+            scx: 0x00,
+            ly: 0x00,
+            lyc: 0x00,
+            bgp: 0x00,
+            obp0: 0x00,
+            obp1: 0x00,
+            wy: 0x00,
+            wx: 0x00,
+            mode: Mode::HBlank,
+            dot: 0,
+            window_line: 0,
+            window_y_hit: false,
+            window_active: false,
+            lx: 0,
+            position: FIRST_POSITION,
+            shifted: None,
+            lcd_pixel: None,
+            startup: 0,
+            fetcher: Fetcher::new(),
+            bg_fifo: VecDeque::with_capacity(16),
+            sprites: Vec::with_capacity(SPRITES_PER_LINE),
+            sprite_fetch: None,
+            sprite_dots: 0,
+            sprite_tile: None,
+            obj_enable: false,
+            obj_fifo: VecDeque::with_capacity(8),
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+            unsafe_lcd_off: false,
+            first_line: false,
+            skip_frame: false,
+            lyc_match: false,
+            stat_line: false,
+            stat_write: false,
+            cgb_hardware: false,
+        }
+    }
+
+    /// Selects CGB hardware, which doesn't have the STAT write bug; even
+    /// when running DMG cartridges
+    pub fn set_cgb_hardware(&mut self, cgb_hardware: bool) {
+        self.cgb_hardware = cgb_hardware;
+    }
+
+    /// Sets up the state left by the boot ROM: the LCD is on and the PPU is
+    /// near the end of line 153, where LY already reads 0.
+    pub fn skip_boot(&mut self) {
+        self.ly = 0;
+        self.dot = 400;
+        self.mode = Mode::VBlank;
+        self.first_line = false;
+        self.skip_frame = false;
+        self.lyc_match = self.lyc == 0;
+    }
+
+    /// Returns the last drawn frame as 160x144 shades from 0 (white) to
+    /// 3 (black), row by row.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Returns if a new frame has been completed since the last call
+    pub fn frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
+    /// Returns if the game has ever turned the LCD off outside of V-Blank,
+    /// which can damage the screen of a real DMG
+    pub fn unsafe_lcd_off(&self) -> bool {
+        self.unsafe_lcd_off
+    }
+
+    /// Returns the current mode of the PPU as seen by the CPU; the last few
+    /// pixels of a line are drawn while it already reads mode 0.
+    pub fn mode(&self) -> Mode {
+        match self.mode {
+            Mode::Drawing if self.lx as usize >= SCREEN_WIDTH - HBLANK_READ_EARLY as usize => Mode::HBlank,
+            mode => mode,
+        }
+    }
+
+    /// Returns if the CPU can access OAM; it's locked during the OAM scan
+    /// and while drawing.
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcd_enabled() || self.mode() == Mode::HBlank || self.mode() == Mode::VBlank
+    }
+
+    /// Returns if the LCD is enabled (LCDC bit 7)
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    /// Reads one of the PPU registers (0xFF40 - 0xFF4B)
+    pub fn rb(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = self.lyc_match as u8;
+                let mode = if self.lcd_enabled() { self.mode() as u8 } else { 0 };
+                0x80 | self.stat | coincidence << 2 | mode
+            },
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    /// Sets one of the PPU registers (0xFF40 - 0xFF4B) without the side
+    /// effects of a write; LCDC doesn't turn the LCD on or off and LY is
+    /// writable.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF40 => { self.lcdc = val; },
+            0xFF41 => { self.stat = val & 0x78; },
+            0xFF44 => { self.ly = val; },
+            _ => { self.wb(addr, val); },
+        }
+    }
+
+    /// Writes one of the PPU registers (0xFF40 - 0xFF4B)
+    pub fn wb(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = val;
+                if was_enabled && !self.lcd_enabled() {
+                    self.disable_lcd();
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.enable_lcd();
+                }
+            },
+            0xFF41 => {
+                self.stat = val & 0x78;
+                self.stat_write = !self.cgb_hardware && self.lcd_enabled();
+            },
+            0xFF42 => { self.scy = val; },
+            0xFF43 => { self.scx = val; },
+            0xFF44 => { }, // read only
+            0xFF45 => { self.lyc = val; },
+            0xFF47 => { self.bgp = val; },
+            0xFF48 => { self.obp0 = val; },
+            0xFF49 => { self.obp1 = val; },
+            0xFF4A => { self.wy = val; },
+            0xFF4B => { self.wx = val; },
+            _ => { },
+        }
+    }
+
+    /// Turns the LCD off; LY & the mode are reset and the screen goes white
+    fn disable_lcd(&mut self) {
+        if self.mode != Mode::VBlank {
+            self.unsafe_lcd_off = true;
+        }
+        self.ly = 0;
+        self.dot = 0;
+        self.window_line = 0;
+        self.window_y_hit = false;
+        self.mode = Mode::HBlank;
+        self.first_line = false;
+        self.stat_line = false;
+        for shade in self.frame.iter_mut() {
+            *shade = 0;
+        }
+        self.frame_ready = true;
+    }
+
+    /// Turns the LCD on, starting the shortened first line in mode 0
+    fn enable_lcd(&mut self) {
+        self.ly = 0;
+        self.dot = FIRST_LINE_SKIP;
+        self.mode = Mode::HBlank;
+        self.first_line = true;
+        self.skip_frame = true;
+    }
+
+    /// Advances the PPU by `dots` dots
+    /// `vram` is the 8KB of video ram at [8000 -> 9FFF] and `oam` the 160
+    /// bytes of sprite attributes at [FE00 -> FE9F].
+    pub fn step(&mut self, dots: u32, vram: &[u8], oam: &[u8], int: &mut InterruptController) {
+        if !self.lcd_enabled() {
+            return;
+        }
+        for _ in 0..dots {
+            self.dot += 1;
+            if self.dot == LINE_DOTS {
+                self.dot = 0;
+                self.next_line();
+            }
+            match self.mode {
+                // The first line after turning the LCD on skips the OAM scan
+                Mode::HBlank if self.first_line && self.dot == OAM_SCAN_DOTS => {
+                    self.sprites.clear();
+                    self.start_drawing();
+                },
+                Mode::HBlank if self.dot == MODE_DELAY && self.ly < SCREEN_HEIGHT as u8 => {
+                    self.mode = Mode::OamScan;
+                },
+                Mode::HBlank if self.dot == MODE_DELAY => {
+                    if self.skip_frame {
+                        self.skip_frame = false;
+                    } else {
+                        self.frame_ready = true;
+                    }
+                    int.request(Interrupt::VBlank);
+                    self.mode = Mode::VBlank;
+                },
+                Mode::OamScan if self.dot == MODE_DELAY + OAM_SCAN_DOTS => {
+                    self.scan_oam(oam);
+                    self.start_drawing();
+                },
+                Mode::Drawing => {
+                    self.draw_dot(vram);
+                    if self.lx as usize == SCREEN_WIDTH {
+                        if self.window_active {
+                            self.window_line += 1;
+                        }
+                        self.mode = Mode::HBlank;
+                    }
+                },
+                // LY reads 0 for most of line 153
+                Mode::VBlank if self.ly == FRAME_LINES - 1 && self.dot == LYC_DELAY => {
+                    self.ly = 0;
+                },
+                _ => { },
+            }
+            self.check_window_y();
+            self.update_stat(int);
+        }
+    }
+
+    /// Compares WY to LY; the window is drawn on the rest of the frame once
+    /// they matched. They are compared at the start of line 0 and twice
+    /// around the end of the other lines, with the old & the new LY.
+    fn check_window_y(&mut self) {
+        let enabled = self.lcdc & 0x20 != 0;
+        match self.dot {
+            MODE_DELAY if self.ly == 0 && self.mode == Mode::OamScan => {
+                self.window_y_hit = enabled && self.wy == 0;
+            },
+            WINDOW_Y_DOT if self.ly < SCREEN_HEIGHT as u8 - 1 => {
+                self.window_y_hit |= enabled && self.ly == self.wy;
+            },
+            dot if dot == (WINDOW_Y_DOT + 4) % LINE_DOTS && self.ly > 0 && self.ly < SCREEN_HEIGHT as u8 => {
+                self.window_y_hit |= enabled && self.ly == self.wy;
+            },
+            _ => { },
+        }
+    }
+
+    /// Moves on to the next line. LY changes a few dots before the mode
+    /// switch into the OAM scan or the vertical blank, during which the
+    /// mode reads as 0.
+    fn next_line(&mut self) {
+        self.first_line = false;
+        if self.mode == Mode::VBlank && self.ly == 0 {
+            // end of line 153, LY already went back to 0
+            self.window_line = 0;
+            self.window_y_hit = false;
+            self.mode = Mode::HBlank;
+        } else {
+            self.ly += 1;
+        }
+    }
+
+    /// Updates the LY=LYC comparison and the STAT interrupt line, requesting
+    /// an interrupt on its rising edge.
+    fn update_stat(&mut self, int: &mut InterruptController) {
+        self.lyc_match = match (self.mode, self.ly) {
+            // line 153 still compares against 153 for a few dots after LY
+            // went back to 0
+            (Mode::VBlank, 0) if self.dot < 2 * LYC_DELAY => self.lyc == FRAME_LINES - 1,
+            (Mode::VBlank, 0) if self.dot < 3 * LYC_DELAY => false,
+            (_, ly) if self.dot < LYC_DELAY && ly != 0 => false,
+            (_, ly) => ly == self.lyc,
+        };
+        // The interrupt source keeps comparing the previous line until the
+        // mode switch
+        let lyc_ly = match (self.mode, self.ly) {
+            (Mode::VBlank, 0) if self.dot < 3 * LYC_DELAY => FRAME_LINES - 1,
+            (_, 0) => 0,
+            (_, ly) if self.dot < MODE_DELAY => ly - 1,
+            (_, ly) => ly,
+        };
+
+        let stat = if self.stat_write { 0x58 } else { self.stat };
+        self.stat_write = false;
+        let line_start = self.dot < MODE_DELAY && !self.first_line;
+        let mode_source = match self.mode {
+            // the mode 2 interrupt is signalled as soon as LY changes, except
+            // for line 0; the mode 0 (for line 0 the mode 1) source stays
+            // active until the mode switch
+            Mode::HBlank if line_start && self.ly == 0 => stat & 0x10 != 0,
+            Mode::HBlank if line_start && self.ly < SCREEN_HEIGHT as u8 => stat & 0x28 != 0,
+            // the first line after turning the LCD on doesn't signal mode 0
+            // until the end of mode 3
+            Mode::HBlank => stat & 0x08 != 0 && !(self.first_line && self.dot < OAM_SCAN_DOTS),
+            Mode::VBlank => stat & 0x10 != 0,
+            Mode::OamScan => stat & 0x20 != 0,
+            Mode::Drawing => {
+                stat & 0x08 != 0 && self.lx as usize >= SCREEN_WIDTH - HBLANK_IRQ_EARLY as usize
+            },
+        };
+        let line = mode_source || (stat & 0x40 != 0 && lyc_ly == self.lyc);
+        if line && !self.stat_line {
+            int.request(Interrupt::LcdStat);
+        }
+        self.stat_line = line;
+    }
+
+    /// Returns if the window is enabled
+    /// On the DMG, LCDC bit 0 disables both the background & the window.
+    fn window_enabled(&self) -> bool {
+        self.lcdc & 0x21 == 0x21 && self.wx < 167
+    }
+
+    /// Returns the height of sprites; 8 or 16 depending on LCDC bit 2
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    /// Selects the first 10 sprites in OAM which are on the current line
+    fn scan_oam(&mut self, oam: &[u8]) {
+        let height = self.sprite_height();
+        let line = self.ly as u16 + 16;
+        self.sprites.clear();
+        for entry in oam.chunks(4) {
+            let y = entry[0] as u16;
+            if line >= y && line < y + height as u16 {
+                self.sprites.push(Sprite {
+                    y: entry[0],
+                    x: entry[1],
+                    tile: entry[2],
+                    flags: entry[3],
+                    fetched: false,
+                });
+                if self.sprites.len() == SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sets up the pixel FIFO & fetcher at the start of mode 3
+    fn start_drawing(&mut self) {
+        self.mode = Mode::Drawing;
+        self.window_active = false;
+        self.lx = 0;
+        self.shifted = None;
+        self.lcd_pixel = None;
+        self.startup = STARTUP_DOTS;
+        self.restart_fetch();
+        self.sprite_fetch = None;
+        self.sprite_tile = None;
+        self.obj_fifo.clear();
+    }
+
+    /// Fills the FIFO with the 8 pixels which are thrown away at the start
+    /// of a line & restarts the fetcher on the first tile
+    fn restart_fetch(&mut self) {
+        self.position = FIRST_POSITION;
+        self.fetcher = Fetcher::new();
+        self.bg_fifo.clear();
+        self.bg_fifo.extend([0; 8].iter());
+    }
+
+    /// Returns the index of the next sprite which starts at the current
+    /// pixel; the one with the lowest x coordinate and for equal x the
+    /// lowest OAM index.
+    fn next_sprite(&self) -> Option<usize> {
+        self.sprites.iter()
+            .enumerate()
+            .filter(|&(_, s)| !s.fetched && self.sprite_reached(s))
+            .min_by_key(|&(i, s)| (s.x, i))
+            .map(|(i, _)| i)
+    }
+
+    /// Sprites off the left edge are reached while the first tile is thrown
+    /// away, the others once their first pixel is about to reach the LCD.
+    fn sprite_reached(&self, sprite: &Sprite) -> bool {
+        sprite.x < 8 || self.position >= 0 && sprite.x <= self.lx + 8
+    }
+
+    /// Starts fetching sprite `index`. The background fetcher first has to
+    /// finish the tile it's working on (0 - 5 dots, only for the first sprite
+    /// on a tile), after which the sprite's tile data takes 6 dots to fetch.
+    fn start_sprite_fetch(&mut self, index: usize) {
+        let sprite_x = self.sprites[index].x;
+        let x = if sprite_x < 8 {
+            // Sprites off the left edge are on the tile left of the screen
+            sprite_x.wrapping_add(self.scx).wrapping_sub(8)
+        } else if self.window_active {
+            self.lx.wrapping_add(7).wrapping_sub(self.wx)
+        } else {
+            self.lx.wrapping_add(self.scx)
+        };
+        let tile = x / 8;
+        let wait = if self.sprite_tile == Some(tile) { 0 } else { 5u8.saturating_sub(x & 0x07) };
+        self.sprite_tile = Some(tile);
+        self.sprite_fetch = Some(index);
+        self.sprite_dots = wait + SPRITE_FETCH_DOTS;
+    }
+
+    /// Runs a single dot of a sprite fetch, no pixels are shifted out in the
+    /// meantime. Clearing LCDC bit 1 aborts the fetch; the dots spent on it
+    /// are still lost.
+    fn sprite_fetch_dot(&mut self, vram: &[u8], index: usize) {
+        if self.lcdc & 0x02 == 0 {
+            self.sprites[index].fetched = true;
+            self.sprite_fetch = None;
+            return;
+        }
+        self.fetch_dot(vram);
+        self.sprite_dots -= 1;
+        if self.sprite_dots == 0 {
+            self.merge_sprite(vram, index);
+            self.sprite_fetch = None;
+        }
+    }
+
+    /// Fetches the row of sprite `index` on the current line and merges it
+    /// into the sprite FIFO.
+    fn merge_sprite(&mut self, vram: &[u8], index: usize) {
+        let sprite = self.sprites[index];
+        self.sprites[index].fetched = true;
+        let height = self.sprite_height();
+        // LCDC bit 2 may have changed since the OAM scan
+        let mut row = (self.ly as u16 + 16 - sprite.y as u16) as u8 & (height - 1);
+        if sprite.flags & 0x40 != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let addr = tile as usize * 16 + row as usize * 2;
+        let (lo, hi) = (vram[addr], vram[addr + 1]);
+        // Pixels of sprites partially off the left edge are dropped
+        let skip = 8u8.saturating_sub(sprite.x);
+        for i in skip..8 {
+            let bit = if sprite.flags & 0x20 != 0 { i } else { 7 - i };
+            let pixel = SpritePixel {
+                color: ((hi >> bit) & 0x01) << 1 | (lo >> bit) & 0x01,
+                palette: sprite.flags & 0x10 != 0,
+                bg_priority: sprite.flags & 0x80 != 0,
+            };
+            let slot = (i - skip) as usize;
+            if slot < self.obj_fifo.len() {
+                if self.obj_fifo[slot].color == 0 {
+                    self.obj_fifo[slot] = pixel;
+                }
+            } else {
+                self.obj_fifo.push_back(pixel);
+            }
+        }
+    }
+
+    /// Runs a single dot of mode 3
+    fn draw_dot(&mut self, vram: &[u8]) {
+        // Sprites are checked with LCDC bit 1 as it was a dot earlier
+        let obj_enable = self.obj_enable;
+        self.obj_enable = self.lcdc & 0x02 != 0;
+        if self.startup > 0 {
+            self.startup -= 1;
+            return;
+        }
+        if self.position < SCREEN_WIDTH as i16 && !self.window_active && self.window_y_hit &&
+            self.window_enabled() && self.position >= -8 && self.position + 7 >= self.wx as i16 {
+            self.window_active = true;
+            self.fetcher = Fetcher::new();
+            self.bg_fifo.clear();
+        }
+        if !obj_enable {
+            // Sprites reached while they're disabled are skipped for good
+            for i in 0..self.sprites.len() {
+                if self.sprite_reached(&self.sprites[i]) {
+                    self.sprites[i].fetched = true;
+                }
+            }
+        } else if self.sprite_fetch.is_none() {
+            if let Some(index) = self.next_sprite() {
+                self.start_sprite_fetch(index);
+            }
+        }
+        if let Some(index) = self.sprite_fetch {
+            self.sprite_fetch_dot(vram, index);
+            return;
+        }
+        // A pixel gets its background shade a dot after it's shifted out
+        // and reaches the LCD another dot later, mixed with the sprites
+        if let Some((color, shade)) = self.lcd_pixel.take() {
+            let sprite = self.obj_fifo.pop_front();
+            self.frame[self.ly as usize * SCREEN_WIDTH + self.lx as usize] = self.shade(color, shade, sprite);
+            self.lx += 1;
+        }
+        if let Some(color) = self.shifted.take() {
+            // With LCDC bit 0 cleared the background & window are white
+            let color = if self.lcdc & 0x01 != 0 { color } else { 0 };
+            self.lcd_pixel = Some((color, (self.bgp >> (color * 2)) & 0x03));
+        }
+        if self.position as usize == SCREEN_WIDTH {
+            // the last pixel is on its way to the LCD
+            return;
+        }
+        self.fetch_dot(vram);
+        self.shift_pixel();
+    }
+
+    /// Shifts a pixel out of the background FIFO, throwing away the pixels
+    /// left of the screen.
+    fn shift_pixel(&mut self) {
+        if self.position < -8 {
+            if self.position & 0x07 == (self.scx & 0x07) as i16 {
+                self.position = -8;
+            } else if self.position == -9 {
+                self.restart_fetch();
+                if self.scx & 0x07 == 0 {
+                    self.position = -8;
+                }
+            }
+        }
+        if let Some(color) = self.bg_fifo.pop_front() {
+            if self.position >= 0 {
+                self.shifted = Some(color);
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Runs a single dot of the background & window fetcher
+    fn fetch_dot(&mut self, vram: &[u8]) {
+        self.fetcher.dots += 1;
+        match self.fetcher.step {
+            FetchStep::Tile if self.fetcher.dots == 1 => {
+                self.fetcher.tile_num = vram[self.tile_map_addr()];
+            },
+            FetchStep::Tile if self.fetcher.dots == 2 => {
+                self.fetcher.step = FetchStep::DataLow;
+                self.fetcher.dots = 0;
+            },
+            FetchStep::DataLow if self.fetcher.dots == 2 => {
+                self.fetcher.lo = vram[self.tile_data_addr()];
+                self.fetcher.step = FetchStep::DataHigh;
+                self.fetcher.dots = 0;
+            },
+            FetchStep::DataHigh if self.fetcher.dots == 2 => {
+                self.fetcher.hi = vram[self.tile_data_addr() + 1];
+                self.fetcher.step = FetchStep::Push;
+                self.fetcher.dots = 0;
+            },
+            FetchStep::Push if self.bg_fifo.is_empty() => {
+                for bit in (0..8).rev() {
+                    let lo = (self.fetcher.lo >> bit) & 0x01;
+                    let hi = (self.fetcher.hi >> bit) & 0x01;
+                    self.bg_fifo.push_back(hi << 1 | lo);
+                }
+                self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+                self.fetcher.step = FetchStep::Tile;
+                self.fetcher.dots = 0;
+            },
+            _ => { },
+        }
+    }
+
+    /// Returns the offset in VRAM of the tile number the fetcher reads next
+    fn tile_map_addr(&self) -> usize {
+        if self.window_active {
+            let map = if self.lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+            let row = self.window_line as usize / 8;
+            map + row * 32 + (self.fetcher.tile_x as usize & 0x1F)
+        } else {
+            let map = if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+            let row = self.ly.wrapping_add(self.scy) as usize / 8;
+            // the first tile is taken from SCX alone, the others from
+            // SCX & the pixel being shifted out
+            let x = if self.position < -8 { 0 } else { self.position + 8 };
+            let col = ((self.scx as i16 + x) as usize / 8) & 0x1F;
+            map + row * 32 + col
+        }
+    }
+
+    /// Returns the offset in VRAM of the low byte of the tile row the
+    /// fetcher reads next.
+    fn tile_data_addr(&self) -> usize {
+        let y = if self.window_active {
+            self.window_line
+        } else {
+            self.ly.wrapping_add(self.scy)
+        };
+        // Tile data is either at [8000 -> 8FFF] with unsigned tile numbers
+        // or at [8800 -> 97FF] with signed tile numbers based at 0x9000.
+        let tile_num = self.fetcher.tile_num;
+        let tile = if self.lcdc & 0x10 != 0 {
+            tile_num as usize * 16
+        } else {
+            (0x1000 + (tile_num as i8 as i32) * 16) as usize
+        };
+        tile + (y as usize % 8) * 2
+    }
+
+    /// Mixes a background pixel of `color` & `shade` with the sprite pixel
+    /// reaching the LCD with it, returning the shade shown
+    fn shade(&self, color: u8, shade: u8, sprite: Option<SpritePixel>) -> u8 {
+        let sprite = sprite
+            .filter(|p| p.color != 0 && self.lcdc & 0x02 != 0)
+            .filter(|p| !p.bg_priority || color == 0);
+        let shade = match sprite {
+            Some(p) => {
+                let palette = if p.palette { self.obp1 } else { self.obp0 };
+                (palette >> (p.color * 2)) & 0x03
+            },
+            None => shade,
+        };
+        if self.skip_frame { 0 } else { shade }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PPU with its VRAM, OAM & interrupt flags
+    struct Lcd {
+        ppu: PPU,
+        vram: Vec<u8>,
+        oam: Vec<u8>,
+        int: InterruptController,
+    }
+
+    impl Lcd {
+        /// Returns an LCD which was just turned on, with empty VRAM & OAM
+        fn new() -> Lcd {
+            let mut ppu = PPU::new();
+            ppu.wb(0xFF47, 0xE4);
+            ppu.wb(0xFF40, 0x91);
+            Lcd { ppu, vram: vec![0; 0x2000], oam: vec![0; 0xA0], int: InterruptController::new() }
+        }
+
+        fn step(&mut self, dots: u32) {
+            self.ppu.step(dots, &self.vram, &self.oam, &mut self.int);
+        }
+
+        /// Puts sprite `index` at OAM coordinates `x` & `y` with `tile`
+        fn sprite(&mut self, index: usize, x: u8, y: u8, tile: u8) {
+            self.oam[index * 4..index * 4 + 4].copy_from_slice(&[y, x, tile, 0x00]);
+        }
+
+        /// Runs until the mode read from STAT is `mode` on line `ly`
+        fn run_to(&mut self, ly: u8, mode: Mode) {
+            while self.ppu.rb(0xFF44) != ly || self.ppu.mode() != mode {
+                self.step(1);
+            }
+        }
+
+        /// Returns the number of STAT interrupts requested until line `ly`
+        fn stat_irqs(&mut self, ly: u8) -> u32 {
+            let mut irqs = 0;
+            while self.ppu.rb(0xFF44) != ly {
+                self.step(1);
+                if self.int.read_if() & 0x02 != 0 {
+                    self.int.acknowledge(Interrupt::LcdStat);
+                    irqs += 1;
+                }
+            }
+            irqs
+        }
+
+        /// Returns the dots STAT reads mode 3 on line `ly`
+        fn mode3_length(&mut self, ly: u8) -> u32 {
+            self.run_to(ly, Mode::Drawing);
+            let mut dots = 0;
+            while self.ppu.mode() == Mode::Drawing {
+                self.step(1);
+                dots += 1;
+            }
+            dots
+        }
+    }
+
+    #[test]
+    fn scx_lengthens_mode_3() {
+        let mut lcd = Lcd::new();
+        let length = lcd.mode3_length(1);
+        assert_eq!(length, 172 - HBLANK_READ_EARLY as u32);
+        for scx in 1..16 {
+            lcd.ppu.wb(0xFF43, scx);
+            assert_eq!(lcd.mode3_length(scx + 1), length + (scx & 0x07) as u32, "SCX {}", scx);
+        }
+    }
+
+    #[test]
+    fn lcd_off_and_on() {
+        let mut lcd = Lcd::new();
+        lcd.run_to(10, Mode::Drawing);
+        lcd.ppu.wb(0xFF40, 0x11);
+        assert_eq!(lcd.ppu.rb(0xFF44), 0);
+        assert_eq!(lcd.ppu.rb(0xFF41) & 0x03, 0);
+        lcd.step(1000);
+        assert_eq!(lcd.ppu.rb(0xFF44), 0);
+
+        // The first line has no OAM scan and is 4 dots shorter
+        lcd.ppu.wb(0xFF40, 0x91);
+        assert_eq!(lcd.ppu.rb(0xFF41) & 0x03, 0);
+        lcd.step(OAM_SCAN_DOTS - FIRST_LINE_SKIP - 1);
+        assert_eq!(lcd.ppu.rb(0xFF41) & 0x03, 0);
+        lcd.step(1);
+        assert_eq!(lcd.ppu.rb(0xFF41) & 0x03, 3);
+        lcd.step(LINE_DOTS - OAM_SCAN_DOTS - 1);
+        assert_eq!(lcd.ppu.rb(0xFF44), 0);
+        lcd.step(1);
+        assert_eq!(lcd.ppu.rb(0xFF44), 1);
+    }
+
+    #[test]
+    fn sprites_lengthen_mode_3() {
+        let mut lcd = Lcd::new();
+        lcd.ppu.wb(0xFF40, 0x93);
+        let length = lcd.mode3_length(1);
+        // 6 dots for the sprite, plus up to 5 for the background tile
+        for &(x, penalty) in &[(0, 11), (8, 11), (9, 10), (12, 7), (13, 6), (15, 6), (16, 11)] {
+            lcd.sprite(0, x, 16, 0);
+            assert_eq!(lcd.mode3_length(2) - length, penalty, "x {}", x);
+            lcd.run_to(3, Mode::HBlank);
+        }
+        // Only the first sprite on a tile waits for the background
+        lcd.sprite(1, 16, 16, 0);
+        assert_eq!(lcd.mode3_length(4) - length, 11 + 6);
+        // Sprites reached while they're disabled are skipped
+        lcd.ppu.wb(0xFF40, 0x91);
+        assert_eq!(lcd.mode3_length(5), length);
+    }
+
+    #[test]
+    fn ten_sprites_per_line() {
+        let mut lcd = Lcd::new();
+        lcd.ppu.wb(0xFF40, 0x93);
+        lcd.ppu.wb(0xFF48, 0xE4);
+        lcd.vram[0x10..0x20].copy_from_slice(&[0xFF; 16]);
+        for i in 0..11 {
+            lcd.sprite(i, 8 + i as u8 * 12, 16, 1);
+        }
+        // the first frame after turning the LCD on isn't shown
+        lcd.step(LINE_DOTS * FRAME_LINES as u32);
+        lcd.run_to(1, Mode::OamScan);
+        let frame = lcd.ppu.frame();
+        for i in 0..11 {
+            assert_eq!(frame[i * 12], if i < 10 { 3 } else { 0 }, "sprite {}", i);
+        }
+    }
+
+    #[test]
+    fn lower_x_has_priority() {
+        let mut lcd = Lcd::new();
+        lcd.ppu.wb(0xFF40, 0x93);
+        lcd.ppu.wb(0xFF48, 0xE4);
+        // tile 1 is color 3, tile 2 color 1
+        lcd.vram[0x10..0x20].copy_from_slice(&[0xFF; 16]);
+        lcd.vram[0x20..0x30].copy_from_slice(&[0xFF, 0x00].repeat(8));
+        lcd.sprite(0, 12, 16, 2);
+        lcd.sprite(1, 8, 16, 1);
+        // for equal x the first one in OAM wins
+        lcd.sprite(2, 40, 16, 2);
+        lcd.sprite(3, 40, 16, 1);
+        // the first frame after turning the LCD on isn't shown
+        lcd.step(LINE_DOTS * FRAME_LINES as u32);
+        lcd.run_to(1, Mode::OamScan);
+        let frame = lcd.ppu.frame();
+        assert_eq!(&frame[0..12], &[3, 3, 3, 3, 3, 3, 3, 3, 1, 1, 1, 1]);
+        assert_eq!(&frame[32..40], &[1; 8]);
+    }
+
+    #[test]
+    fn stat_sources_block_each_other() {
+        let mut lcd = Lcd::new();
+        lcd.run_to(1, Mode::OamScan);
+        lcd.ppu.wb(0xFF41, 0x08);
+        lcd.run_to(2, Mode::OamScan);
+        lcd.int.write_if(0x00);
+        assert_eq!(lcd.stat_irqs(12), 10);
+        lcd.ppu.wb(0xFF41, 0x20);
+        lcd.run_to(12, Mode::Drawing);
+        lcd.int.write_if(0x00);
+        assert_eq!(lcd.stat_irqs(22), 10);
+        // The mode 0 source is still active when mode 2 starts
+        lcd.ppu.wb(0xFF41, 0x28);
+        lcd.run_to(22, Mode::Drawing);
+        lcd.int.write_if(0x00);
+        assert_eq!(lcd.stat_irqs(32), 10);
+        // LY=LYC holds the line high during mode 0 of line 40
+        lcd.ppu.wb(0xFF45, 40);
+        lcd.ppu.wb(0xFF41, 0x48);
+        lcd.run_to(32, Mode::Drawing);
+        lcd.int.write_if(0x00);
+        assert_eq!(lcd.stat_irqs(42), 9);
+    }
+
+    #[test]
+    fn stat_write_requests_an_interrupt() {
+        let mut lcd = Lcd::new();
+        lcd.run_to(1, Mode::HBlank);
+        lcd.int.write_if(0x00);
+        lcd.ppu.wb(0xFF41, 0x00);
+        lcd.step(1);
+        assert_eq!(lcd.int.read_if() & 0x02, 0x02);
+
+        // Not during mode 3 or on a CGB
+        lcd.run_to(2, Mode::Drawing);
+        lcd.int.write_if(0x00);
+        lcd.ppu.wb(0xFF41, 0x00);
+        lcd.step(1);
+        assert_eq!(lcd.int.read_if() & 0x02, 0x00);
+        lcd.ppu.set_cgb_hardware(true);
+        lcd.run_to(2, Mode::HBlank);
+        lcd.ppu.wb(0xFF41, 0x00);
+        lcd.step(1);
+        assert_eq!(lcd.int.read_if() & 0x02, 0x00);
+    }
+}
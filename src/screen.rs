@@ -1,5 +1,10 @@
-/// The Gameboy screen is a 160x144 pixel display with 20x18 tiles.
-/// The screen size is 2.6 inches which is about 6.6 centimeters.
+//! The Gameboy screen is a 160x144 pixel display with 20x18 tiles.
+//! The screen size is 2.6 inches which is about 6.6 centimeters.
+
+/// Width of the screen in pixels
+pub const SCREEN_WIDTH: usize = 160;
+/// Height of the screen in pixels
+pub const SCREEN_HEIGHT: usize = 144;
 
 // TODO: sort this info as it probably will have nothing to do with the screen's
 // emulation.
@@ -0,0 +1,219 @@
+// The serial port shifts out the byte in SB one bit at a time (most
+// significant bit first) while shifting in the bits of the other Game Boy.
+//
+// Registers:
+// 0xFF01 : SB - Serial Transfer Data
+// 0xFF02 : SC - Serial Transfer Control
+//          7 - Transfer Start Flag (0=No Transfer, 1=Start or Transfer in progress)
+//          1 - Clock Speed         (0=Normal, 1=Fast) (CGB Only)
+//          0 - Shift Clock         (0=External Clock, 1=Internal Clock)
+//
+// With the internal clock the Game Boy drives the transfer at 8192 Hz (512
+// clock cycles per bit), or at 262144 Hz (16 clock cycles per bit) in the
+// CGB's fast mode. With the external clock the transfer waits until the other
+// side clocks the bits in, which may be never. Once all 8 bits are exchanged
+// bit 7 of SC is cleared and the serial interrupt is requested.
+//
+// The other side of the cable is a `Link`; without one every bit shifted in
+// reads as 1, just like an unconnected cable.
+
+use interrupt::{Interrupt, InterruptController};
+
+/// Clock cycles per bit at 8192 Hz
+const BIT_CYCLES: u32 = 512;
+/// Clock cycles per bit at 262144 Hz (CGB fast mode)
+const FAST_BIT_CYCLES: u32 = 16;
+
+/// The other end of the link cable
+pub trait Link {
+    /// Called when the Game Boy starts a transfer with its internal clock;
+    /// `byte` is the byte being sent and the returned byte is shifted in.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Polled while the Game Boy waits for an external clock with `byte` in
+    /// SB; returns the byte the other side sends once it starts clocking.
+    fn external(&mut self, byte: u8) -> Option<u8> {
+        let _ = byte;
+        None
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    incoming: u8, // bits left to shift into SB
+    bits: u8, // bits left in the current transfer
+    cycles: u32, // clock cycles since the last shifted bit
+    link: Option<Box<dyn Link>>,
+    cgb_mode: bool,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
+            incoming: 0xFF,
+            bits: 0,
+            cycles: 0,
+            link: None,
+            cgb_mode: false,
+        }
+    }
+
+    /// Enables the CGB's fast clock speed
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+    }
+
+    /// Plugs `link` into the serial port, replacing the previous one
+    pub fn connect(&mut self, link: Box<dyn Link>) {
+        self.link = Some(link);
+    }
+
+    /// Unplugs the link cable
+    pub fn disconnect(&mut self) -> Option<Box<dyn Link>> {
+        self.link.take()
+    }
+
+    /// Advances the serial port by `cycles` clock cycles
+    pub fn step(&mut self, cycles: u32, int: &mut InterruptController) {
+        if self.sc & 0x80 == 0 {
+            return;
+        }
+        if self.sc & 0x01 == 0 {
+            // Wait for the other side to clock the transfer
+            let sb = self.sb;
+            if let Some(byte) = self.link.as_mut().and_then(|link| link.external(sb)) {
+                self.sb = byte;
+                self.finish(int);
+            }
+            return;
+        }
+        self.cycles += cycles;
+        let period = self.bit_cycles();
+        while self.bits > 0 && self.cycles >= period {
+            self.cycles -= period;
+            self.sb = self.sb << 1 | self.incoming >> 7;
+            self.incoming <<= 1;
+            self.bits -= 1;
+            if self.bits == 0 {
+                self.finish(int);
+            }
+        }
+    }
+
+    /// Reads one of the serial registers (0xFF01 - 0xFF02)
+    pub fn rb(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 if self.cgb_mode => self.sc | 0x7C,
+            0xFF02 => self.sc | 0x7E,
+            _ => 0xFF,
+        }
+    }
+
+    /// Sets one of the serial registers (0xFF01 - 0xFF02) without starting a
+    /// transfer
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => { self.sb = val; },
+            0xFF02 => { self.sc = val & 0x83; },
+            _ => { },
+        }
+    }
+
+    /// Writes one of the serial registers (0xFF01 - 0xFF02)
+    pub fn wb(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => { self.sb = val; },
+            0xFF02 => {
+                self.sc = val & 0x83;
+                if val & 0x81 == 0x81 {
+                    self.start();
+                }
+            },
+            _ => { },
+        }
+    }
+
+    /// Starts a transfer with the internal clock
+    fn start(&mut self) {
+        let sb = self.sb;
+        self.incoming = match self.link {
+            Some(ref mut link) => link.transfer(sb),
+            None => 0xFF,
+        };
+        self.bits = 8;
+        self.cycles = 0;
+    }
+
+    /// Ends the transfer and requests the serial interrupt
+    fn finish(&mut self, int: &mut InterruptController) {
+        self.sc &= 0x7F;
+        self.bits = 0;
+        int.request(Interrupt::Serial);
+    }
+
+    /// Returns the clock cycles per bit of the internal clock
+    fn bit_cycles(&self) -> u32 {
+        if self.cgb_mode && self.sc & 0x02 != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            BIT_CYCLES
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A link which answers every transfer with `0x42`
+    struct Answer;
+
+    impl Link for Answer {
+        fn transfer(&mut self, _byte: u8) -> u8 {
+            0x42
+        }
+    }
+
+    #[test]
+    fn internal_clock_interrupts_after_8_bits() {
+        let mut int = InterruptController::new();
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Answer));
+        serial.wb(0xFF01, 0x81);
+        serial.wb(0xFF02, 0x81);
+        serial.step(8 * BIT_CYCLES - 1, &mut int);
+        assert_eq!(serial.rb(0xFF02), 0xFF);
+        assert_eq!(int.read_if() & 0x08, 0x00);
+        serial.step(1, &mut int);
+        assert_eq!(serial.rb(0xFF01), 0x42);
+        assert_eq!(serial.rb(0xFF02), 0x7F);
+        assert_eq!(int.read_if() & 0x08, 0x08);
+    }
+
+    #[test]
+    fn unconnected_shifts_in_ones() {
+        let mut int = InterruptController::new();
+        let mut serial = Serial::new();
+        serial.wb(0xFF01, 0x00);
+        serial.wb(0xFF02, 0x81);
+        serial.step(4 * BIT_CYCLES, &mut int);
+        assert_eq!(serial.rb(0xFF01), 0x0F);
+        serial.step(4 * BIT_CYCLES, &mut int);
+        assert_eq!(serial.rb(0xFF01), 0xFF);
+        assert_eq!(int.read_if() & 0x08, 0x08);
+    }
+
+    #[test]
+    fn external_clock_waits() {
+        let mut int = InterruptController::new();
+        let mut serial = Serial::new();
+        serial.wb(0xFF02, 0x80);
+        serial.step(100 * BIT_CYCLES, &mut int);
+        assert_eq!(serial.rb(0xFF02) & 0x80, 0x80);
+        assert_eq!(int.read_if() & 0x08, 0x00);
+    }
+}
@@ -0,0 +1,245 @@
+// The timer is driven by a 16-bit divider which is incremented every clock
+// cycle; DIV is its upper byte.
+//
+// Registers:
+// 0xFF04 : DIV  - Divider Register (writing any value resets it to 0)
+// 0xFF05 : TIMA - Timer Counter
+// 0xFF06 : TMA  - Timer Modulo (loaded into TIMA when it overflows)
+// 0xFF07 : TAC  - Timer Control
+//          2   - Timer Enable                (0=Stop, 1=Start)
+//          1-0 - Input Clock Select          (divider bit)
+//                00 -   4096 Hz              (bit 9)
+//                01 - 262144 Hz              (bit 3)
+//                10 -  65536 Hz              (bit 5)
+//                11 -  16384 Hz              (bit 7)
+//
+// TIMA isn't clocked by a counter of its own; the selected divider bit is
+// AND-ed with the timer enable bit and TIMA is incremented on the falling
+// edge of that signal. This is why writing DIV (which clears the divider) or
+// writing TAC (which changes the selected bit or disables the timer) can
+// increment TIMA when the signal was high.
+//
+// When TIMA overflows it reads 0x00 for one M-cycle (4 clock cycles) before
+// it's reloaded from TMA and the timer interrupt is requested. Writing TIMA
+// during that cycle cancels the reload & the interrupt. During the M-cycle of
+// the reload writes to TIMA are ignored, while writes to TMA are also copied
+// into TIMA.
+
+use interrupt::{Interrupt, InterruptController};
+
+/// Clock cycles between the overflow of TIMA and its reload from TMA
+const OVERFLOW_DELAY: u8 = 4;
+/// Clock cycles after the reload in which TIMA follows TMA
+const RELOAD_CYCLES: u8 = 4;
+
+pub struct Timer {
+    div: u16, // internal divider; DIV is the upper byte
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    overflow: u8, // cycles left until TIMA is reloaded, 0 = no overflow
+    reload: u8, // cycles left of the reload M-cycle
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            div: 0x0000,
+            tima: 0x00,
+            tma: 0x00,
+            tac: 0x00,
+            overflow: 0,
+            reload: 0,
+        }
+    }
+
+    /// Advances the timer by `cycles` clock cycles
+    pub fn step(&mut self, cycles: u32, int: &mut InterruptController) {
+        for _ in 0..cycles {
+            if self.reload > 0 {
+                self.reload -= 1;
+            }
+            if self.overflow > 0 {
+                self.overflow -= 1;
+                if self.overflow == 0 {
+                    self.tima = self.tma;
+                    self.reload = RELOAD_CYCLES;
+                    int.request(Interrupt::Timer);
+                }
+            }
+            let signal = self.signal();
+            self.div = self.div.wrapping_add(1);
+            if signal && !self.signal() {
+                self.increment();
+            }
+        }
+    }
+
+    /// Clears the divider, as done by writing DIV or executing `STOP`
+    pub fn reset_div(&mut self) {
+        let signal = self.signal();
+        self.div = 0;
+        if signal {
+            self.increment();
+        }
+    }
+
+    /// Returns the internal divider
+    pub fn div(&self) -> u16 {
+        self.div
+    }
+
+    /// Sets the internal divider, used to skip the boot ROM
+    pub fn set_div(&mut self, div: u16) {
+        self.div = div;
+    }
+
+    /// Reads one of the timer registers (0xFF04 - 0xFF07)
+    pub fn rb(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.div >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => 0xFF,
+        }
+    }
+
+    /// Sets one of the timer registers (0xFF04 - 0xFF07) without the side
+    /// effects of a write; DIV sets the upper byte of the internal divider.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF04 => { self.div = (val as u16) << 8; },
+            0xFF05 => { self.tima = val; },
+            0xFF06 => { self.tma = val; },
+            0xFF07 => { self.tac = val & 0x07; },
+            _ => { },
+        }
+    }
+
+    /// Writes one of the timer registers (0xFF04 - 0xFF07)
+    pub fn wb(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF04 => { self.reset_div(); },
+            // ignored while TIMA is being reloaded
+            0xFF05 if self.reload == 0 => {
+                self.tima = val;
+                self.overflow = 0;
+            },
+            0xFF06 => {
+                self.tma = val;
+                if self.reload > 0 {
+                    self.tima = val;
+                }
+            },
+            0xFF07 => {
+                let signal = self.signal();
+                self.tac = val & 0x07;
+                if signal && !self.signal() {
+                    self.increment();
+                }
+            },
+            _ => { },
+        }
+    }
+
+    /// Returns the divider bit selected by TAC AND-ed with the enable bit
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.div & (1 << bit) != 0
+    }
+
+    /// Increments TIMA, starting the reload delay when it overflows
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.overflow = OVERFLOW_DELAY;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a timer counting on divider bit 3, every 16 cycles
+    fn timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.wb(0xFF07, 0x05);
+        timer
+    }
+
+    #[test]
+    fn counts_on_the_falling_edge() {
+        let mut int = InterruptController::new();
+        let mut timer = timer();
+        timer.step(15, &mut int);
+        assert_eq!(timer.rb(0xFF05), 0x00);
+        timer.step(1, &mut int);
+        assert_eq!(timer.rb(0xFF05), 0x01);
+        timer.step(256, &mut int);
+        assert_eq!(timer.rb(0xFF05), 0x11);
+        assert_eq!(timer.rb(0xFF04), 0x01);
+    }
+
+    #[test]
+    fn div_and_tac_writes_can_increment() {
+        let mut timer = timer();
+        timer.set_div(0x0008);
+        timer.wb(0xFF04, 0x00);
+        assert_eq!(timer.rb(0xFF05), 0x01);
+        // bit 3 is low, nothing happens
+        timer.wb(0xFF04, 0x00);
+        assert_eq!(timer.rb(0xFF05), 0x01);
+
+        // Switching to a low bit or disabling the timer is a falling edge
+        timer.set_div(0x0008);
+        timer.wb(0xFF07, 0x04);
+        assert_eq!(timer.rb(0xFF05), 0x02);
+        timer.wb(0xFF07, 0x05);
+        timer.wb(0xFF07, 0x01);
+        assert_eq!(timer.rb(0xFF05), 0x03);
+    }
+
+    #[test]
+    fn overflow_reloads_after_a_delay() {
+        let mut int = InterruptController::new();
+        let mut timer = timer();
+        timer.wb(0xFF05, 0xFF);
+        timer.wb(0xFF06, 0x42);
+        timer.step(16, &mut int);
+        timer.step(3, &mut int);
+        assert_eq!(timer.rb(0xFF05), 0x00);
+        assert_eq!(int.read_if() & 0x04, 0x00);
+        timer.step(1, &mut int);
+        assert_eq!(timer.rb(0xFF05), 0x42);
+        assert_eq!(int.read_if() & 0x04, 0x04);
+
+        // TIMA follows TMA & ignores writes while it's reloaded
+        timer.wb(0xFF05, 0x10);
+        timer.wb(0xFF06, 0x24);
+        assert_eq!(timer.rb(0xFF05), 0x24);
+        timer.step(4, &mut int);
+        timer.wb(0xFF05, 0x10);
+        assert_eq!(timer.rb(0xFF05), 0x10);
+    }
+
+    #[test]
+    fn tima_write_cancels_the_reload() {
+        let mut int = InterruptController::new();
+        let mut timer = timer();
+        timer.wb(0xFF05, 0xFF);
+        timer.wb(0xFF06, 0x42);
+        timer.step(17, &mut int);
+        timer.wb(0xFF05, 0x10);
+        timer.step(4, &mut int);
+        assert_eq!(timer.rb(0xFF05), 0x10);
+        assert_eq!(int.read_if() & 0x04, 0x00);
+    }
+}
@@ -0,0 +1,487 @@
+//! Runs the gambatte hardware test ROMs in `test/gb_test_roms`. The ROMs
+//! with an `_out<result>` name print their result as hex digits at the top
+//! left of the screen, which is compared to the expected one. The others are
+//! compared with the reference screenshots of a DMG in
+//! `3rd_party/gambatte-master/test/hwtests`, like gambatte's own test runner.
+//! A ROM without a DMG result makes the run fail, unless it's one of those
+//! gambatte doesn't check on a DMG either (see `NO_DMG_RESULT`).
+//!
+//! These take a while, so they only run with `cargo test -- --ignored`.
+
+extern crate rgb_emu;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rgb_emu::emulator_context;
+
+/// Frames to run before reading the result
+const FRAMES: u32 = 16;
+
+/// Test ROMs for which gambatte has neither an `_out` result nor a DMG
+/// screenshot; they were only verified on a CGB, or not at all. The
+/// `scx_during_m3_*` ROMs are the old `scx_during_m3/old/offset_3` builds,
+/// the DMG screenshots of the same name belong to the newer tests.
+const NO_DMG_RESULT: [&str; 54] = [
+    "ime_noie_nolcdirq_readstat_dmg08_cgb_blank.gb",
+    "late_disable_group_image_1.gb",
+    "late_disable_group_image_2.gb",
+    "late_disable_group_image_3.gb",
+    "late_disable_group_image_4.gb",
+    "late_disable_group_image_5.gb",
+    "late_disable_group_image_6.gb",
+    "late_disable_group_image_7.gb",
+    "late_disable_group_image_8.gb",
+    "late_disable_group_image_9.gb",
+    "late_disable_scx5_1.gb",
+    "late_disable_scx5_2.gb",
+    "late_disable_sp00x18_1.gb",
+    "late_disable_sp00x18_2.gb",
+    "lycint_lycirq_1.gb",
+    "lycint_lycirq_2.gb",
+    "lycint_m0stat_1.gb",
+    "lycint_m0stat_2.gb",
+    "lycirq98_m0stat_1.gb",
+    "lycirq98_m0stat_2.gb",
+    "lycirq_lycirq_1.gb",
+    "lycirq_lycirq_2.gb",
+    "lycirq_m0stat_1.gb",
+    "lycirq_m0stat_2.gb",
+    "lycirq_scy_during_m3_1.gb",
+    "lycirq_scy_during_m3_2.gb",
+    "lycirq_scy_during_m3_3.gb",
+    "lycirq_scy_during_m3_4.gb",
+    "lycirq_scy_during_m3_5.gb",
+    "m2int_m2irq_1.gb",
+    "m2int_m2irq_2.gb",
+    "m2int_m2stat_1.gb",
+    "m2int_m2stat_2.gb",
+    "m2int_nobg_m3stat_1_cgb04c_out3.gb",
+    "m2int_nobg_m3stat_2_cgb04c_out0.gb",
+    "m2int_nobg_scx7_m3stat_1_cgb04c_out3.gb",
+    "m2irq_ly_1.gb",
+    "m2irq_ly_2.gb",
+    "m2irq_m2irq_1.gb",
+    "m2irq_m2irq_2.gb",
+    "m2irq_m2stat_1.gb",
+    "m2irq_m2stat_2.gb",
+    "m2irq_scy_during_m3_1.gb",
+    "m2irq_scy_during_m3_2.gb",
+    "m2irq_scy_during_m3_3.gb",
+    "m2irq_scy_during_m3_4.gb",
+    "m2irq_scy_during_m3_5.gb",
+    "noime_noie_nolcdirq_readstat_dmg08_cgb_blank.gb",
+    "scx_during_m3_1.gb",
+    "scx_during_m3_2.gb",
+    "scx_during_m3_3.gb",
+    "scx_during_m3_4.gb",
+    "scx_during_m3_5.gb",
+    "sprite_late_enable_spx19_2_dmg08_xout0.gb",
+];
+
+/// Tiles of the hex digits 0 - F as the test ROMs draw them
+const DIGITS: [[u8; 8]; 16] = [
+    [0x00, 0x7F, 0x41, 0x41, 0x41, 0x41, 0x41, 0x7F],
+    [0x00, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x7F, 0x01, 0x01, 0x7F, 0x40, 0x40, 0x7F],
+    [0x00, 0x7F, 0x01, 0x01, 0x3F, 0x01, 0x01, 0x7F],
+    [0x00, 0x41, 0x41, 0x41, 0x7F, 0x01, 0x01, 0x01],
+    [0x00, 0x7F, 0x40, 0x40, 0x7E, 0x01, 0x01, 0x7E],
+    [0x00, 0x7F, 0x40, 0x40, 0x7F, 0x41, 0x41, 0x7F],
+    [0x00, 0x7F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x10],
+    [0x00, 0x3E, 0x41, 0x41, 0x3E, 0x41, 0x41, 0x3E],
+    [0x00, 0x7F, 0x41, 0x41, 0x7F, 0x01, 0x01, 0x7F],
+    [0x00, 0x08, 0x22, 0x41, 0x7F, 0x41, 0x41, 0x41],
+    [0x00, 0x7E, 0x41, 0x41, 0x7E, 0x41, 0x41, 0x7E],
+    [0x00, 0x3E, 0x41, 0x40, 0x40, 0x40, 0x41, 0x3E],
+    [0x00, 0x7E, 0x41, 0x41, 0x41, 0x41, 0x41, 0x7E],
+    [0x00, 0x7F, 0x40, 0x40, 0x7F, 0x40, 0x40, 0x7F],
+    [0x00, 0x7F, 0x40, 0x40, 0x7F, 0x40, 0x40, 0x40],
+];
+
+/// What a test ROM should leave on the screen
+enum Expected {
+    Digits(String),
+    /// Screenshots in 160x144 shades; some exist in several variants for
+    /// different register values, the frame has to match one of them
+    Screens(Vec<Vec<u8>>),
+}
+
+/// Returns the expected DMG result of a test ROM from its name, e.g. "3"
+/// for `sprite_late_enable_spx18_1_dmg08_out3.gb`
+fn expected_digits(name: &str) -> Option<&str> {
+    let stem = name.trim_end_matches(".gb");
+    let start = stem.find("dmg08_cgb04c_out").map(|i| i + 16)
+        .or_else(|| stem.find("dmg08_out").map(|i| i + 9))?;
+    let out = stem[start..].split('_').next().unwrap_or("");
+    if out.is_empty() || out.contains("audio") {
+        None
+    } else {
+        Some(out)
+    }
+}
+
+/// Returns the expected result of the test ROM `name`, looking up its
+/// screenshots in `screens`
+fn expected(name: &str, screens: &HashMap<String, Vec<PathBuf>>) -> Option<Expected> {
+    if let Some(out) = expected_digits(name) {
+        return Some(Expected::Digits(out.to_string()));
+    }
+    let stem = name.trim_end_matches(".gb");
+    let paths = screens.get(&format!("{}_dmg08_cgb04c.png", stem))
+        .or_else(|| screens.get(&format!("{}_dmg08.png", stem)))?;
+    Some(Expected::Screens(paths.iter().map(|path| png::shades(&fs::read(path).unwrap())).collect()))
+}
+
+/// Lists the screenshots below `dir` by file name
+fn find_screens(dir: &Path, screens: &mut HashMap<String, Vec<PathBuf>>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_screens(&path, screens);
+        } else if path.extension().is_some_and(|ext| ext == "png") {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            screens.entry(name).or_default().push(path);
+        }
+    }
+}
+
+/// Reads the hex digit drawn in the `index`th tile of the top row
+fn digit(frame: &[u8], index: usize) -> Option<char> {
+    let mut rows = [0u8; 8];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for x in 0..8 {
+            match frame[y * 160 + index * 8 + x] {
+                0 => { },
+                3 => *row |= 0x80 >> x,
+                _ => return None,
+            }
+        }
+    }
+    DIGITS.iter().position(|d| *d == rows)
+        .and_then(|d| std::char::from_digit(d as u32, 16))
+        .map(|c| c.to_ascii_uppercase())
+}
+
+/// Runs the test ROM at `path`, returning the last frame
+fn run(path: &Path) -> Vec<u8> {
+    let mut ctx = emulator_context::new();
+    ctx.load_file(path).unwrap();
+    ctx.skip_boot_rom();
+    let mut frames = 0;
+    while frames < FRAMES {
+        ctx.step().unwrap();
+        if ctx.frame_ready() {
+            frames += 1;
+        }
+    }
+    ctx.framebuffer().to_vec()
+}
+
+/// Checks `frame` against the expected result, describing the difference
+fn check(frame: &[u8], expected: &Expected) -> Result<(), String> {
+    match *expected {
+        Expected::Digits(ref out) => {
+            let got: String = (0..out.len()).map(|i| digit(frame, i).unwrap_or('?')).collect();
+            if got == *out { Ok(()) } else { Err(format!("got {}", got)) }
+        },
+        Expected::Screens(ref screens) => {
+            let diffs = screens.iter()
+                .map(|screen| screen.iter().zip(frame).filter(|&(a, b)| a != b).count());
+            match diffs.min() {
+                Some(0) => Ok(()),
+                diff => Err(format!("{} pixels differ", diff.unwrap_or(0))),
+            }
+        },
+    }
+}
+
+/// Runs every test ROM in the directory whose name contains `filter`
+fn run_all(filter: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = root.join("test/gb_test_roms");
+    let mut screens = HashMap::new();
+    find_screens(&root.join("3rd_party/gambatte-master/test/hwtests"), &mut screens);
+
+    let mut names: Vec<String> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".gb") && name.contains(filter))
+        .collect();
+    names.sort();
+
+    let mut failures = Vec::new();
+    let mut count = 0;
+    let mut unchecked = 0;
+    for name in &names {
+        if NO_DMG_RESULT.contains(&name.as_str()) {
+            unchecked += 1;
+            continue;
+        }
+        let expected = match expected(name, &screens) {
+            Some(expected) => expected,
+            None => {
+                failures.push(format!("{}: no expected result", name));
+                continue;
+            },
+        };
+        count += 1;
+        if let Err(err) = check(&run(&dir.join(name)), &expected) {
+            failures.push(format!("{}: {}", name, err));
+        }
+    }
+    println!("{} test roms checked, {} without a DMG result", count, unchecked);
+    assert!(count > 0 || !failures.is_empty(), "no test roms matching {:?}", filter);
+    assert!(failures.is_empty(), "{} of {} failed:\n{}",
+            failures.len(), count, failures.join("\n"));
+}
+
+/// Just enough of PNG (RFC 2083) and zlib (RFC 1950 & 1951) to read the
+/// 8-bit RGBA screenshots of gambatte's tests
+mod png {
+    /// Base lengths & extra bits of the length codes 257 - 285
+    const LENGTHS: [(u16, u8); 29] = [
+        (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 0),
+        (11, 1), (13, 1), (15, 1), (17, 1), (19, 2), (23, 2), (27, 2), (31, 2),
+        (35, 3), (43, 3), (51, 3), (59, 3), (67, 4), (83, 4), (99, 4), (115, 4),
+        (131, 5), (163, 5), (195, 5), (227, 5), (258, 0),
+    ];
+    /// Base distances & extra bits of the distance codes 0 - 29
+    const DISTANCES: [(u16, u8); 30] = [
+        (1, 0), (2, 0), (3, 0), (4, 0), (5, 1), (7, 1), (9, 2), (13, 2),
+        (17, 3), (25, 3), (33, 4), (49, 4), (65, 5), (97, 5), (129, 6), (193, 6),
+        (257, 7), (385, 7), (513, 8), (769, 8), (1025, 9), (1537, 9),
+        (2049, 10), (3073, 10), (4097, 11), (6145, 11), (8193, 12), (12289, 12),
+        (16385, 13), (24577, 13),
+    ];
+    /// Order of the code length code lengths of a dynamic block
+    const CODE_LENGTH_ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    struct Bits<'a> {
+        data: &'a [u8],
+        pos: usize, // in bits
+    }
+
+    impl<'a> Bits<'a> {
+        fn bit(&mut self) -> u32 {
+            let bit = self.data[self.pos / 8] >> (self.pos % 8) & 1;
+            self.pos += 1;
+            bit as u32
+        }
+
+        fn bits(&mut self, count: u8) -> u32 {
+            (0..count).fold(0, |val, i| val | self.bit() << i)
+        }
+
+        fn align(&mut self) {
+            self.pos = self.pos.div_ceil(8) * 8;
+        }
+    }
+
+    /// A canonical Huffman code
+    struct Huffman {
+        counts: [u16; 16], // codes of each length
+        symbols: Vec<u16>, // ordered by code
+    }
+
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Huffman {
+            let mut counts = [0; 16];
+            for &len in lengths {
+                counts[len as usize] += 1;
+            }
+            counts[0] = 0;
+            let mut offsets = [0; 16];
+            for len in 1..15 {
+                offsets[len + 1] = offsets[len] + counts[len];
+            }
+            let mut symbols = vec![0; lengths.len()];
+            for (symbol, &len) in lengths.iter().enumerate() {
+                if len != 0 {
+                    symbols[offsets[len as usize] as usize] = symbol as u16;
+                    offsets[len as usize] += 1;
+                }
+            }
+            Huffman { counts, symbols }
+        }
+
+        fn decode(&self, bits: &mut Bits) -> u16 {
+            let (mut code, mut first, mut index) = (0, 0, 0);
+            for len in 1..16 {
+                code |= bits.bit() as i32;
+                let count = self.counts[len] as i32;
+                if code - first < count {
+                    return self.symbols[(index + code - first) as usize];
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            panic!("invalid huffman code");
+        }
+    }
+
+    /// Decompresses a zlib stream
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut bits = Bits { data: &data[2..], pos: 0 };
+        let mut out = Vec::new();
+        loop {
+            let last = bits.bit() == 1;
+            match bits.bits(2) {
+                0 => {
+                    bits.align();
+                    let start = bits.pos / 8;
+                    let len = data[2 + start] as usize | (data[3 + start] as usize) << 8;
+                    out.extend_from_slice(&data[6 + start..6 + start + len]);
+                    bits.pos += (4 + len) * 8;
+                },
+                1 => {
+                    let mut lengths = [8; 288];
+                    lengths[144..256].iter_mut().for_each(|len| *len = 9);
+                    lengths[256..280].iter_mut().for_each(|len| *len = 7);
+                    inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]));
+                },
+                2 => {
+                    let (literals, distances) = dynamic_codes(&mut bits);
+                    inflate_block(&mut bits, &mut out, &literals, &distances);
+                },
+                _ => panic!("invalid block type"),
+            }
+            if last {
+                return out;
+            }
+        }
+    }
+
+    /// Reads the codes of a dynamic Huffman block
+    fn dynamic_codes(bits: &mut Bits) -> (Huffman, Huffman) {
+        let literals = bits.bits(5) as usize + 257;
+        let distances = bits.bits(5) as usize + 1;
+        let code_lengths = bits.bits(4) as usize + 4;
+        let mut lengths = [0; 19];
+        for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[index] = bits.bits(3) as u8;
+        }
+        let code = Huffman::new(&lengths);
+
+        let mut lengths = Vec::with_capacity(literals + distances);
+        while lengths.len() < literals + distances {
+            match code.decode(bits) {
+                len @ 0..=15 => lengths.push(len as u8),
+                16 => {
+                    let prev = *lengths.last().unwrap();
+                    let repeat = 3 + bits.bits(2);
+                    lengths.extend((0..repeat).map(|_| prev));
+                },
+                17 => {
+                    let repeat = 3 + bits.bits(3);
+                    lengths.extend((0..repeat).map(|_| 0));
+                },
+                _ => {
+                    let repeat = 11 + bits.bits(7);
+                    lengths.extend((0..repeat).map(|_| 0));
+                },
+            }
+        }
+        (Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..]))
+    }
+
+    fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) {
+        loop {
+            let symbol = literals.decode(bits) as usize;
+            if symbol < 256 {
+                out.push(symbol as u8);
+            } else if symbol == 256 {
+                return;
+            } else {
+                let (base, extra) = LENGTHS[symbol - 257];
+                let len = (base as u32 + bits.bits(extra)) as usize;
+                let (base, extra) = DISTANCES[distances.decode(bits) as usize];
+                let dist = (base as u32 + bits.bits(extra)) as usize;
+                for _ in 0..len {
+                    let byte = out[out.len() - dist];
+                    out.push(byte);
+                }
+            }
+        }
+    }
+
+    /// Decodes a 160x144 RGBA screenshot into shades from 0 (white) to
+    /// 3 (black)
+    pub fn shades(file: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut pos = 8;
+        while pos + 8 <= file.len() {
+            let len = u32::from_be_bytes([file[pos], file[pos + 1], file[pos + 2], file[pos + 3]]) as usize;
+            let kind = &file[pos + 4..pos + 8];
+            let chunk = &file[pos + 8..pos + 8 + len];
+            if kind == b"IHDR" {
+                // 160x144, 8 bits per channel, RGBA, not interlaced
+                assert_eq!(chunk, [0, 0, 0, 160, 0, 0, 0, 144, 8, 6, 0, 0, 0]);
+            } else if kind == b"IDAT" {
+                compressed.extend_from_slice(chunk);
+            }
+            pos += 12 + len;
+        }
+
+        let data = inflate(&compressed);
+        let stride = 160 * 4;
+        let mut prev = vec![0u8; stride];
+        let mut shades = Vec::with_capacity(160 * 144);
+        for row in data.chunks(stride + 1) {
+            let mut line = row[1..].to_vec();
+            for x in 0..stride {
+                let a = if x >= 4 { line[x - 4] as i16 } else { 0 };
+                let b = prev[x] as i16;
+                let c = if x >= 4 { prev[x - 4] as i16 } else { 0 };
+                let predicted = match row[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => (a + b) / 2,
+                    _ => {
+                        let p = a + b - c;
+                        let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                        if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+                    },
+                };
+                line[x] = line[x].wrapping_add(predicted as u8);
+            }
+            shades.extend(line.chunks(4).map(|pixel| 3 - pixel[0] / 0x55));
+            prev = line;
+        }
+        shades
+    }
+}
+
+#[test]
+#[ignore]
+fn sprite_late() {
+    run_all("sprite_late_");
+}
+
+#[test]
+#[ignore]
+fn late_disable() {
+    run_all("late_disable_");
+}
+
+#[test]
+#[ignore]
+fn start_inc() {
+    run_all("start_inc_");
+}
+
+#[test]
+#[ignore]
+fn during_m3() {
+    run_all("_during_m3_");
+}
+
+#[test]
+#[ignore]
+fn all() {
+    run_all("");
+}